@@ -3,7 +3,7 @@ use std::net::{Ipv4Addr, SocketAddrV4};
 extern crate igd;
 
 fn main() {
-    match igd::search_gateway() {
+    match igd::sync::search_gateway() {
         Err(ref err) => println!("Error: {}", err),
         Ok(gateway) => {
             let local_addr = match std::env::args().nth(1) {
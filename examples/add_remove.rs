@@ -4,7 +4,7 @@ use std::net::SocketAddrV4;
 extern crate igd;
 
 fn main() {
-    match igd::search_gateway() {
+    match igd::sync::search_gateway() {
         Err(ref err) => match *err {
             igd::SearchError::IoError(ref ioe) => println!("IoError: {}", ioe),
             _ => println!("{:?}", err),
@@ -8,48 +8,38 @@
 //! If everything works fine, 2 port mappings are added, 1 removed and we're left with single
 //! port mapping: External 1234 ---> 4321 Internal
 
-extern crate futures;
 extern crate igd;
-extern crate tokio_core;
+extern crate tokio;
 
-use futures::future::Future;
-use igd::tokio::search_gateway;
+use igd::search_gateway;
 use igd::PortMappingProtocol;
 
-fn main() {
-    let mut evloop = tokio_core::reactor::Core::new().unwrap();
-    let handle = evloop.handle();
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let gateway = match search_gateway().await {
+        Ok(gateway) => gateway,
+        Err(e) => panic!("Failed to find IGD: {}", e),
+    };
 
-    let task = search_gateway(&handle)
-        .map_err(|e| panic!("Failed to find IGD: {}", e))
-        .and_then(|gateway| gateway.get_external_ip().map_err(|e| panic!("Failed to get external IP: {}", e)).and_then(|ip| Ok((gateway, ip))))
-        .and_then(|(gateway, pub_ip)| {
-            println!("Our public IP: {}", pub_ip);
-            Ok(gateway)
-        })
-        .and_then(|gateway| {
-            gateway
-                .add_port(PortMappingProtocol::TCP, 1234, "192.168.1.210:4321".parse().unwrap(), 0, "rust-igd-async-example")
-                .map_err(|e| panic!("Failed to add port mapping: {}", e))
-                .and_then(|_| {
-                    println!("New port mapping was successfully added.");
-                    Ok(gateway)
-                })
-        })
-        .and_then(|gateway| {
-            gateway
-                .add_port(PortMappingProtocol::TCP, 2345, "192.168.1.210:5432".parse().unwrap(), 0, "rust-igd-async-example")
-                .map_err(|e| panic!("Failed to add port mapping: {}", e))
-                .and_then(|_| {
-                    println!("New port mapping was successfully added.");
-                    Ok(gateway)
-                })
-        })
-        .and_then(|gateway| gateway.remove_port(PortMappingProtocol::TCP, 2345))
-        .and_then(|_| {
-            println!("Port was removed.");
-            Ok(())
-        });
+    match gateway.get_external_ip().await {
+        Ok(pub_ip) => println!("Our public IP: {}", pub_ip),
+        Err(e) => panic!("Failed to get external IP: {}", e),
+    }
 
-    let _ = evloop.run(task).unwrap();
+    let local_addr = "192.168.1.210:4321".parse().unwrap();
+    match gateway.add_port(PortMappingProtocol::TCP, 1234, local_addr, 0, "rust-igd-async-example").await {
+        Ok(()) => println!("New port mapping was successfully added."),
+        Err(e) => panic!("Failed to add port mapping: {}", e),
+    }
+
+    let local_addr = "192.168.1.210:5432".parse().unwrap();
+    match gateway.add_port(PortMappingProtocol::TCP, 2345, local_addr, 0, "rust-igd-async-example").await {
+        Ok(()) => println!("New port mapping was successfully added."),
+        Err(e) => panic!("Failed to add port mapping: {}", e),
+    }
+
+    match gateway.remove_port(PortMappingProtocol::TCP, 2345).await {
+        Ok(()) => println!("Port was removed."),
+        Err(e) => panic!("Failed to remove port mapping: {}", e),
+    }
 }
@@ -1,7 +1,7 @@
 extern crate igd;
 
 fn main() {
-    match igd::search_gateway() {
+    match igd::sync::search_gateway() {
         Err(ref err) => println!("Error: {}", err),
         Ok(gateway) => match gateway.get_external_ip() {
             Err(ref err) => {
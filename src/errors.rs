@@ -1,45 +1,44 @@
-use std;
+// The Fail derive implements its traits inside a constant.
+#![allow(non_local_definitions)]
+
 use std::io;
 use std::str;
+use std::string::FromUtf8Error;
 
-use hyper;
 use xml::reader::Error as XmlError;
 
+use crate::runtime::Elapsed;
 use crate::soap;
-use tokio;
-use std::string::FromUtf8Error;
 
 /// Errors that can occur when sending the request to the gateway.
 #[derive(Debug, Fail)]
 pub enum RequestError {
     /// Http/Hyper error
-    #[fail(display = "HTTP error: _0")]
+    #[fail(display = "HTTP error: {}", _0)]
     HttpError(hyper::Error),
     /// IO Error
-    #[fail(display = "IO error: _0")]
+    #[fail(display = "IO error: {}", _0)]
     IoError(io::Error),
     /// The response from the gateway could not be parsed.
-    #[fail(display = "Invalid response from gateway: _0")]
+    #[fail(display = "Invalid response from gateway: {}", _0)]
     InvalidResponse(String),
     /// The gateway returned an unhandled error code and description.
-    #[fail(display = "Gateway response error _0: _1")]
+    #[fail(display = "Gateway response error {}: {}", _0, _1)]
     ErrorCode(u16, String),
-    /// Tokio timer error
-    #[fail(display = "The port was not mapped")]
-    TimerError(tokio::timer::Error),
     /// UTF-8 decoding error
-    #[fail(display = "UTF-8 error: _0")]
+    #[fail(display = "UTF-8 error: {}", _0)]
     Utf8Error(FromUtf8Error),
     /// Invalid URI
-    #[fail(display = "Invalid URI error: _0")]
+    #[fail(display = "Invalid URI error: {}", _0)]
     InvalidUri(hyper::http::uri::InvalidUri),
+    /// Error of a request made with NAT-PMP by a `PortMapper`
+    #[fail(display = "NAT-PMP error: {}", _0)]
+    NatPmpError(NatPmpError),
+    /// Error of a request made with PCP by a `PortMapper`
+    #[fail(display = "PCP error: {}", _0)]
+    PcpError(PcpError),
 }
 
-impl From<tokio::timer::Error> for RequestError {
-    fn from(e: tokio::timer::Error) -> Self {
-        RequestError::TimerError(e)
-    }
-}
 /// Errors returned by `Gateway::get_external_ip`
 #[derive(Debug, Fail)]
 pub enum GetExternalIpError {
@@ -47,10 +46,174 @@ pub enum GetExternalIpError {
     #[fail(display = "The client is not authorized to remove the port")]
     ActionNotAuthorized,
     /// Some other error occured performing the request.
-    #[fail(display = "Request Error: _0")]
+    #[fail(display = "Request Error: {}", _0)]
+    RequestError(RequestError),
+}
+
+/// Errors returned by `Gateway::get_generic_port_mapping_entry` and `Gateway::port_mappings`
+#[derive(Debug, Fail)]
+pub enum GetGenericPortMappingEntryError {
+    /// The client is not authorized to perform the operation.
+    #[fail(display = "The client is not authorized to read the port mappings")]
+    ActionNotAuthorized,
+    /// The index is past the end of the port mapping table.
+    #[fail(display = "The specified array index is out of bounds")]
+    SpecifiedArrayIndexInvalid,
+    /// Some other error occured performing the request.
+    #[fail(display = "Request error. {}", _0)]
+    RequestError(RequestError),
+}
+
+/// Errors returned by `Gateway::get_port_mapping`
+#[derive(Debug, Fail)]
+pub enum GetPortMappingError {
+    /// The client is not authorized to perform the operation.
+    #[fail(display = "The client is not authorized to read the port mapping")]
+    ActionNotAuthorized,
+    /// No such port mapping.
+    #[fail(display = "The port is not mapped")]
+    NoSuchEntryInArray,
+    /// Some other error occured performing the request.
+    #[fail(display = "Request error. {}", _0)]
+    RequestError(RequestError),
+}
+
+/// Errors returned by `Gateway::list_port_mappings`
+#[derive(Debug, Fail)]
+pub enum ListPortMappingsError {
+    /// The client is not authorized to perform the operation.
+    #[fail(display = "The client is not authorized to read the port mappings")]
+    ActionNotAuthorized,
+    /// The start port is greater than the end port.
+    #[fail(display = "The start port is greater than the end port")]
+    InconsistentParameters,
+    /// Some other error occured performing the request.
+    #[fail(display = "Request error. {}", _0)]
+    RequestError(RequestError),
+}
+
+/// Errors returned by `Gateway::status_info`
+#[derive(Debug, Fail)]
+pub enum GetStatusInfoError {
+    /// The client is not authorized to perform the operation.
+    #[fail(display = "The client is not authorized to read the connection status")]
+    ActionNotAuthorized,
+    /// Some other error occured performing the request.
+    #[fail(display = "Request error. {}", _0)]
+    RequestError(RequestError),
+}
+
+/// Errors returned by `Gateway::nat_rsip_status`, `Gateway::connection_type_info` and
+/// `Gateway::port_mapping_number_of_entries`
+#[derive(Debug, Fail)]
+pub enum GetConnectionInfoError {
+    /// The client is not authorized to perform the operation.
+    #[fail(display = "The client is not authorized to read the connection information")]
+    ActionNotAuthorized,
+    /// The gateway does not implement the action.
+    #[fail(display = "The gateway does not implement the action")]
+    InvalidAction,
+    /// Some other error occured performing the request.
+    #[fail(display = "Request error. {}", _0)]
     RequestError(RequestError),
 }
 
+impl From<RequestError> for GetConnectionInfoError {
+    fn from(err: RequestError) -> GetConnectionInfoError {
+        match err {
+            RequestError::ErrorCode(401, _) => GetConnectionInfoError::InvalidAction,
+            RequestError::ErrorCode(606, _) => GetConnectionInfoError::ActionNotAuthorized,
+            e => GetConnectionInfoError::RequestError(e),
+        }
+    }
+}
+
+/// Errors returned by the methods of `Gateway` using the WANCommonInterfaceConfig service
+#[derive(Debug, Fail)]
+pub enum GetInterfaceConfigError {
+    /// The gateway does not offer the WANCommonInterfaceConfig service.
+    #[fail(display = "The gateway does not offer the WANCommonInterfaceConfig service")]
+    ServiceNotAvailable,
+    /// The client is not authorized to perform the operation.
+    #[fail(display = "The client is not authorized to read the interface configuration")]
+    ActionNotAuthorized,
+    /// The gateway does not implement the action.
+    #[fail(display = "The gateway does not implement the action")]
+    InvalidAction,
+    /// Some other error occured performing the request.
+    #[fail(display = "Request error. {}", _0)]
+    RequestError(RequestError),
+}
+
+impl From<RequestError> for GetInterfaceConfigError {
+    fn from(err: RequestError) -> GetInterfaceConfigError {
+        match err {
+            RequestError::ErrorCode(401, _) => GetInterfaceConfigError::InvalidAction,
+            RequestError::ErrorCode(606, _) => GetInterfaceConfigError::ActionNotAuthorized,
+            e => GetInterfaceConfigError::RequestError(e),
+        }
+    }
+}
+
+/// Errors returned by the methods of `Gateway` using the WANIPv6FirewallControl service
+#[derive(Debug, Fail)]
+pub enum PinholeError {
+    /// The gateway does not offer the WANIPv6FirewallControl service.
+    #[fail(display = "The gateway does not offer the WANIPv6FirewallControl service")]
+    ServiceNotAvailable,
+    /// The client is not authorized to perform the operation.
+    #[fail(display = "The client is not authorized to manage this pinhole")]
+    ActionNotAuthorized,
+    /// The gateway can not open any more pinholes.
+    #[fail(display = "The gateway can not open any more pinholes")]
+    PinholeSpaceExhausted,
+    /// The firewall of the gateway is disabled.
+    #[fail(display = "The firewall of the gateway is disabled")]
+    FirewallDisabled,
+    /// The gateway does not allow opening inbound pinholes.
+    #[fail(display = "The gateway does not allow opening inbound pinholes")]
+    InboundPinholeNotAllowed,
+    /// There is no pinhole with the given identifier.
+    #[fail(display = "There is no pinhole with the given identifier")]
+    NoSuchEntry,
+    /// The gateway does not support the protocol.
+    #[fail(display = "The gateway does not support the protocol")]
+    ProtocolNotSupported,
+    /// The gateway does not allow a wildcard internal port.
+    #[fail(display = "The gateway does not allow a wildcard internal port")]
+    InternalPortWildcardingNotAllowed,
+    /// The gateway does not allow a wildcard protocol.
+    #[fail(display = "The gateway does not allow a wildcard protocol")]
+    ProtocolWildcardingNotAllowed,
+    /// The gateway does not allow a wildcard source address.
+    #[fail(display = "The gateway does not allow a wildcard source address")]
+    WildCardNotPermittedInSrcIp,
+    /// No traffic went through the pinhole, the gateway can not tell whether it works.
+    #[fail(display = "No traffic went through the pinhole")]
+    NoTrafficReceived,
+    /// Some other error occured performing the request.
+    #[fail(display = "Request error. {}", _0)]
+    RequestError(RequestError),
+}
+
+impl From<RequestError> for PinholeError {
+    fn from(err: RequestError) -> PinholeError {
+        match err {
+            RequestError::ErrorCode(606, _) => PinholeError::ActionNotAuthorized,
+            RequestError::ErrorCode(701, _) => PinholeError::PinholeSpaceExhausted,
+            RequestError::ErrorCode(702, _) => PinholeError::FirewallDisabled,
+            RequestError::ErrorCode(703, _) => PinholeError::InboundPinholeNotAllowed,
+            RequestError::ErrorCode(704, _) => PinholeError::NoSuchEntry,
+            RequestError::ErrorCode(705, _) => PinholeError::ProtocolNotSupported,
+            RequestError::ErrorCode(706, _) => PinholeError::InternalPortWildcardingNotAllowed,
+            RequestError::ErrorCode(707, _) => PinholeError::ProtocolWildcardingNotAllowed,
+            RequestError::ErrorCode(708, _) => PinholeError::WildCardNotPermittedInSrcIp,
+            RequestError::ErrorCode(709, _) => PinholeError::NoTrafficReceived,
+            e => PinholeError::RequestError(e),
+        }
+    }
+}
+
 /// Errors returned by `Gateway::remove_port`
 #[derive(Debug, Fail)]
 pub enum RemovePortError {
@@ -61,7 +224,7 @@ pub enum RemovePortError {
     #[fail(display = "The port was not mapped")]
     NoSuchPortMapping,
     /// Some other error occured performing the request.
-    #[fail(display = "Request error. _0")]
+    #[fail(display = "Request error. {}", _0)]
     RequestError(RequestError),
 }
 
@@ -81,6 +244,12 @@ pub enum AddAnyPortError {
     /// and this external port is in use.
     #[fail(display = "The gateway can only map internal ports to same-numbered external ports and this external port is in use.")]
     ExternalPortInUse,
+    /// External port number 0 (any port) is considered invalid by the gateway.
+    #[fail(display = "External port number 0 (any port) is considered invalid by the gateway.")]
+    ExternalPortZeroInvalid,
+    /// The gateway requires that the requested internal and external ports are the same.
+    #[fail(display = "The gateway requires that the requested internal and external ports are the same.")]
+    SamePortValuesRequired,
     /// The gateway only supports permanent leases (ie. a `lease_duration` of 0).
     #[fail(display = "The gateway only supports permanent leases (ie. a `lease_duration` of 0),")]
     OnlyPermanentLeasesSupported,
@@ -88,7 +257,7 @@ pub enum AddAnyPortError {
     #[fail(display = "The description was too long for the gateway to handle.")]
     DescriptionTooLong,
     /// Some other error occured performing the request.
-    #[fail(display = "Request error. _0")]
+    #[fail(display = "Request error. {}", _0)]
     RequestError(RequestError),
 }
 
@@ -123,7 +292,7 @@ pub enum AddPortError {
     #[fail(display = "The description was too long for the gateway to handle.")]
     DescriptionTooLong,
     /// Some other error occured performing the request.
-    #[fail(display = "Request error. _0")]
+    #[fail(display = "Request error. {}", _0)]
     RequestError(RequestError),
 }
 
@@ -148,22 +317,22 @@ impl From<soap::Error> for RequestError {
 #[derive(Debug, Fail)]
 pub enum SearchError {
     /// Http/Hyper error
-    #[fail(display = "HTTP error: _0")]
+    #[fail(display = "HTTP error: {}", _0)]
     HttpError(hyper::Error),
     /// Unable to process the response
-    #[fail(display = "Invalid URI: _0")]
+    #[fail(display = "Invalid URI: {}", _0)]
     InvalidUri(hyper::http::uri::InvalidUri),
     /// The response from the gateway could not be parsed.
     #[fail(display = "Invalid response")]
     InvalidResponse,
     /// IO Error
-    #[fail(display = "IO error: _0")]
+    #[fail(display = "IO error: {}", _0)]
     IoError(io::Error),
     /// UTF-8 decoding error
-    #[fail(display = "UTF-8 error: _0")]
+    #[fail(display = "UTF-8 error: {}", _0)]
     Utf8Error(str::Utf8Error),
     /// XML processing error
-    #[fail(display = "XML error: _0")]
+    #[fail(display = "XML error: {}", _0)]
     XmlError(XmlError),
 }
 
@@ -197,8 +366,280 @@ impl From<XmlError> for SearchError {
     }
 }
 
-impl From<tokio::timer::timeout::Error<SearchError>> for SearchError {
-    fn from(_err: tokio::timer::timeout::Error<SearchError>) -> SearchError {
+impl From<TransportError> for SearchError {
+    fn from(err: TransportError) -> SearchError {
+        match err {
+            TransportError::HttpError(e) => SearchError::HttpError(e),
+            TransportError::IoError(e) | TransportError::ConnectError(e) => SearchError::IoError(e),
+        }
+    }
+}
+
+impl From<soap::Error> for SearchError {
+    fn from(err: soap::Error) -> SearchError {
+        match err {
+            soap::Error::HttpError(e) => SearchError::HttpError(e),
+            soap::Error::IoError(e) => SearchError::IoError(e),
+            soap::Error::InvalidUri(e) => SearchError::InvalidUri(e),
+            soap::Error::Utf8Error(_) => SearchError::InvalidResponse,
+        }
+    }
+}
+
+impl From<Elapsed> for SearchError {
+    fn from(_err: Elapsed) -> SearchError {
         SearchError::IoError(io::Error::new(io::ErrorKind::TimedOut, "search timed out"))
     }
 }
+
+/// Errors returned by `Gateway::subscribe` and the methods of `Subscription`
+#[derive(Debug, Fail)]
+pub enum SubscribeError {
+    /// The gateway does not publish the events of its connection service.
+    #[fail(display = "The gateway does not publish events")]
+    ServiceNotAvailable,
+    /// The gateway refused the request with the given HTTP status, 412 when it does not know the subscription.
+    #[fail(display = "The gateway refused the request with status {}", _0)]
+    Refused(u16),
+    /// The gateway accepted the subscription without giving its identifier.
+    #[fail(display = "The gateway did not return a subscription identifier")]
+    InvalidResponse,
+    /// Http/Hyper error
+    #[fail(display = "HTTP error: {}", _0)]
+    HttpError(hyper::Error),
+    /// Unable to build the url of the request
+    #[fail(display = "Invalid URI: {}", _0)]
+    InvalidUri(hyper::http::uri::InvalidUri),
+    /// IO Error
+    #[fail(display = "IO error: {}", _0)]
+    IoError(io::Error),
+}
+
+impl From<hyper::Error> for SubscribeError {
+    fn from(err: hyper::Error) -> SubscribeError {
+        SubscribeError::HttpError(err)
+    }
+}
+
+impl From<hyper::http::uri::InvalidUri> for SubscribeError {
+    fn from(err: hyper::http::uri::InvalidUri) -> SubscribeError {
+        SubscribeError::InvalidUri(err)
+    }
+}
+
+impl From<io::Error> for SubscribeError {
+    fn from(err: io::Error) -> SubscribeError {
+        SubscribeError::IoError(err)
+    }
+}
+
+impl From<soap::Error> for SubscribeError {
+    fn from(err: soap::Error) -> SubscribeError {
+        match err {
+            soap::Error::HttpError(e) => SubscribeError::HttpError(e),
+            soap::Error::IoError(e) => SubscribeError::IoError(e),
+            soap::Error::InvalidUri(e) => SubscribeError::InvalidUri(e),
+            soap::Error::Utf8Error(_) => SubscribeError::InvalidResponse,
+        }
+    }
+}
+
+impl From<TransportError> for SubscribeError {
+    fn from(err: TransportError) -> SubscribeError {
+        match err {
+            TransportError::HttpError(e) => SubscribeError::HttpError(e),
+            TransportError::IoError(e) | TransportError::ConnectError(e) => SubscribeError::IoError(e),
+        }
+    }
+}
+
+/// Errors returned by a `Transport`
+#[derive(Debug, Fail)]
+pub enum TransportError {
+    /// Http/Hyper error
+    #[fail(display = "HTTP error: {}", _0)]
+    HttpError(hyper::Error),
+    /// IO Error, also used by transports that are not built on hyper
+    #[fail(display = "IO error: {}", _0)]
+    IoError(io::Error),
+    /// The connection to the gateway could not be made, so the request was not sent
+    #[fail(display = "Connect error: {}", _0)]
+    ConnectError(io::Error),
+}
+
+impl From<hyper::Error> for TransportError {
+    fn from(err: hyper::Error) -> TransportError {
+        TransportError::HttpError(err)
+    }
+}
+
+impl From<io::Error> for TransportError {
+    fn from(err: io::Error) -> TransportError {
+        TransportError::IoError(err)
+    }
+}
+
+/// Errors returned by the methods of `NatPmpGateway`
+#[derive(Debug, Fail)]
+pub enum NatPmpError {
+    /// The gateway does not support the version of the protocol.
+    #[fail(display = "The gateway does not support NAT-PMP version 0")]
+    UnsupportedVersion,
+    /// The gateway refused the request, for instance because mapping is disabled.
+    #[fail(display = "The gateway refused the request")]
+    NotAuthorized,
+    /// The gateway has no external address, for instance because it is not connected.
+    #[fail(display = "The gateway has no external address")]
+    NetworkFailure,
+    /// The gateway can not create any more mappings.
+    #[fail(display = "The gateway can not create any more mappings")]
+    OutOfResources,
+    /// The gateway does not support the operation.
+    #[fail(display = "The gateway does not support the operation")]
+    UnsupportedOpcode,
+    /// The gateway returned a result code not defined by the specification.
+    #[fail(display = "The gateway returned the unknown result code {}", _0)]
+    UnknownResultCode(u16),
+    /// The response of the gateway could not be parsed.
+    #[fail(display = "Invalid response")]
+    InvalidResponse,
+    /// IO Error, of kind `TimedOut` when the gateway does not answer.
+    #[fail(display = "IO error: {}", _0)]
+    IoError(io::Error),
+}
+
+impl From<io::Error> for NatPmpError {
+    fn from(err: io::Error) -> NatPmpError {
+        NatPmpError::IoError(err)
+    }
+}
+
+/// Errors returned by the methods of `PcpGateway`
+#[derive(Debug, Fail)]
+pub enum PcpError {
+    /// The client is not authorized to perform the operation.
+    #[fail(display = "The client is not authorized to map this port.")]
+    ActionNotAuthorized,
+    /// Can not add a mapping for local port 0.
+    #[fail(display = "Can not add a mapping for local port 0")]
+    InternalPortZeroInvalid,
+    /// The external address asked for with `PcpOptions::prefer_failure` is not available.
+    #[fail(display = "The requested mapping conflicts with a mapping assigned to another client.")]
+    PortInUse,
+    /// The gateway does not have any free ports, or the client used up its quota.
+    #[fail(display = "The gateway does not have any free ports")]
+    NoPortsAvailable,
+    /// The gateway has no external address, for instance because it is not connected.
+    #[fail(display = "The gateway has no external address")]
+    NetworkFailure,
+    /// The gateway does not support the version of the protocol.
+    #[fail(display = "The gateway does not support PCP version 2")]
+    UnsupportedVersion,
+    /// The gateway does not support the operation.
+    #[fail(display = "The gateway does not support the operation")]
+    UnsupportedOpcode,
+    /// The gateway does not support an option of the request, such as THIRD_PARTY.
+    #[fail(display = "The gateway does not support an option of the request")]
+    UnsupportedOption,
+    /// The gateway does not support the protocol.
+    #[fail(display = "The gateway does not support the protocol")]
+    UnsupportedProtocol,
+    /// The gateway could not parse the request.
+    #[fail(display = "The gateway could not parse the request")]
+    MalformedRequest,
+    /// The gateway saw the request come from another address than the one it carries, there is
+    /// another NAT between the client and the gateway.
+    #[fail(display = "The request went through another NAT on its way to the gateway")]
+    AddressMismatch,
+    /// The gateway can not create a PEER mapping for that many remote peers.
+    #[fail(display = "The gateway can not create a mapping for that many remote peers")]
+    ExcessiveRemotePeers,
+    /// The gateway returned a result code not defined by the specification.
+    #[fail(display = "The gateway returned the unknown result code {}", _0)]
+    UnknownResultCode(u8),
+    /// The response of the gateway could not be parsed.
+    #[fail(display = "Invalid response")]
+    InvalidResponse,
+    /// IO Error, of kind `TimedOut` when the gateway does not answer.
+    #[fail(display = "IO error: {}", _0)]
+    IoError(io::Error),
+}
+
+impl From<io::Error> for PcpError {
+    fn from(err: io::Error) -> PcpError {
+        PcpError::IoError(err)
+    }
+}
+
+impl From<NatPmpError> for GetExternalIpError {
+    fn from(err: NatPmpError) -> GetExternalIpError {
+        match err {
+            NatPmpError::NotAuthorized => GetExternalIpError::ActionNotAuthorized,
+            e => GetExternalIpError::RequestError(RequestError::NatPmpError(e)),
+        }
+    }
+}
+
+impl From<NatPmpError> for AddAnyPortError {
+    fn from(err: NatPmpError) -> AddAnyPortError {
+        match err {
+            NatPmpError::NotAuthorized => AddAnyPortError::ActionNotAuthorized,
+            NatPmpError::OutOfResources => AddAnyPortError::NoPortsAvailable,
+            e => AddAnyPortError::RequestError(RequestError::NatPmpError(e)),
+        }
+    }
+}
+
+impl From<NatPmpError> for RemovePortError {
+    fn from(err: NatPmpError) -> RemovePortError {
+        match err {
+            NatPmpError::NotAuthorized => RemovePortError::ActionNotAuthorized,
+            e => RemovePortError::RequestError(RequestError::NatPmpError(e)),
+        }
+    }
+}
+
+impl From<PcpError> for GetExternalIpError {
+    fn from(err: PcpError) -> GetExternalIpError {
+        match err {
+            PcpError::ActionNotAuthorized => GetExternalIpError::ActionNotAuthorized,
+            e => GetExternalIpError::RequestError(RequestError::PcpError(e)),
+        }
+    }
+}
+
+impl From<PcpError> for AddAnyPortError {
+    fn from(err: PcpError) -> AddAnyPortError {
+        match err {
+            PcpError::ActionNotAuthorized => AddAnyPortError::ActionNotAuthorized,
+            PcpError::InternalPortZeroInvalid => AddAnyPortError::InternalPortZeroInvalid,
+            PcpError::PortInUse => AddAnyPortError::ExternalPortInUse,
+            PcpError::NoPortsAvailable => AddAnyPortError::NoPortsAvailable,
+            e => AddAnyPortError::RequestError(RequestError::PcpError(e)),
+        }
+    }
+}
+
+impl From<PcpError> for RemovePortError {
+    fn from(err: PcpError) -> RemovePortError {
+        match err {
+            PcpError::ActionNotAuthorized => RemovePortError::ActionNotAuthorized,
+            e => RemovePortError::RequestError(RequestError::PcpError(e)),
+        }
+    }
+}
+
+impl From<AddPortError> for AddAnyPortError {
+    fn from(err: AddPortError) -> AddAnyPortError {
+        match err {
+            AddPortError::ActionNotAuthorized => AddAnyPortError::ActionNotAuthorized,
+            AddPortError::InternalPortZeroInvalid => AddAnyPortError::InternalPortZeroInvalid,
+            AddPortError::ExternalPortZeroInvalid => AddAnyPortError::ExternalPortZeroInvalid,
+            AddPortError::PortInUse => AddAnyPortError::ExternalPortInUse,
+            AddPortError::SamePortValuesRequired => AddAnyPortError::SamePortValuesRequired,
+            AddPortError::OnlyPermanentLeasesSupported => AddAnyPortError::OnlyPermanentLeasesSupported,
+            AddPortError::DescriptionTooLong => AddAnyPortError::DescriptionTooLong,
+            AddPortError::RequestError(e) => AddAnyPortError::RequestError(e),
+        }
+    }
+}
@@ -0,0 +1,397 @@
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket as StdUdpSocket};
+use std::pin::pin;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::channel::oneshot;
+use futures::future::{self, Either};
+use http_body_util::{BodyExt, Full};
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode, Uri};
+
+use crate::errors::SubscribeError;
+use crate::runtime::{self, AsyncTcpListener, TcpListener};
+use crate::{ConnectionStatus, Gateway};
+
+// How long to wait before trying again to renew a subscription after a failure.
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The receiving end of the events of a `Subscription`.
+pub type GatewayEvents = UnboundedReceiver<GatewayEvent>;
+
+/// A change of a state variable of the connection service, as reported by the gateway.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GatewayEvent {
+    /// The external IP address of the gateway changed.
+    ExternalIpAddress(Ipv4Addr),
+    /// The status of the connection changed.
+    ConnectionStatus(ConnectionStatus),
+    /// The number of port mappings changed.
+    PortMappingNumberOfEntries(u16),
+    /// Another state variable changed, or one of the above has a value that can not be parsed.
+    Other {
+        /// The name of the state variable.
+        name: String,
+        /// The new value of the state variable.
+        value: String,
+    },
+}
+
+impl GatewayEvent {
+    fn parse(name: &str, value: &str) -> GatewayEvent {
+        let event = match name {
+            "ExternalIPAddress" => value.parse().ok().map(GatewayEvent::ExternalIpAddress),
+            "ConnectionStatus" => Some(GatewayEvent::ConnectionStatus(ConnectionStatus::parse(value))),
+            "PortMappingNumberOfEntries" => value.parse().ok().map(GatewayEvent::PortMappingNumberOfEntries),
+            _ => None,
+        };
+        event.unwrap_or_else(|| GatewayEvent::Other {
+            name: name.to_owned(),
+            value: value.to_owned(),
+        })
+    }
+}
+
+/// A subscription to the events of the connection service of a gateway.
+///
+/// The subscription is renewed before it expires, and made again if the gateway forgot about it,
+/// for as long as the handle is alive. Dropping the handle cancels the subscription on a
+/// best-effort basis and ends the stream of events.
+pub struct Subscription {
+    shared: Arc<Shared>,
+    // Stops the callback server when dropped.
+    _shutdown: oneshot::Sender<()>,
+}
+
+struct Shared {
+    // The requests are sent with its transport, within its limit of requests in flight.
+    gateway: Gateway,
+    event_url: String,
+    callback: String,
+    requested_timeout: Duration,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    // The identifier given by the gateway, None before it answers and after unsubscribing.
+    sid: Option<String>,
+    // How long the gateway keeps the subscription, None if forever.
+    timeout: Option<Duration>,
+}
+
+impl Gateway {
+    /// Subscribe to the events of the connection service of the gateway.
+    ///
+    /// A small HTTP server is started on the local address the gateway is reached from, and the
+    /// gateway is asked to send its events there. The timeout parameter is the duration of the
+    /// subscription asked for, the gateway may grant another one.
+    ///
+    /// # Returns
+    ///
+    /// The subscription and the stream of its events on success. Otherwise an error.
+    pub async fn subscribe(&self, timeout: Duration) -> Result<(Subscription, GatewayEvents), SubscribeError> {
+        let event_url = match self.event_sub_url() {
+            Some(event_sub_url) => format!("http://{}{}", self.addr(), event_sub_url),
+            None => return Err(SubscribeError::ServiceNotAvailable),
+        };
+        let local_addr = local_addr_towards(self.addr())?;
+        let (events, receiver) = mpsc::unbounded();
+        let state = Arc::new(Mutex::new(State::default()));
+        let listener = TcpListener::bind(local_addr).await?;
+        // Without the scope id, which only means something to this host.
+        let callback = format!("http://{}/", SocketAddr::new(local_addr.ip(), listener.local_addr()?.port()));
+        let (shutdown, signal) = oneshot::channel();
+        runtime::spawn(serve(listener, events, state.clone(), signal));
+
+        let shared = Arc::new(Shared {
+            gateway: self.clone(),
+            event_url,
+            callback,
+            requested_timeout: timeout,
+            state,
+        });
+        if let Some(timeout) = subscribe(&shared).await? {
+            schedule_renewal(Arc::downgrade(&shared), renew_delay(timeout));
+        }
+        let subscription = Subscription {
+            shared,
+            _shutdown: shutdown,
+        };
+        Ok((subscription, receiver))
+    }
+}
+
+impl Subscription {
+    /// get the identifier the gateway gave to the subscription
+    pub fn sid(&self) -> String {
+        self.shared.state.lock().unwrap().sid.clone().unwrap_or_default()
+    }
+
+    /// get how long the gateway keeps the subscription without renewal, None if forever
+    pub fn timeout(&self) -> Option<Duration> {
+        self.shared.state.lock().unwrap().timeout
+    }
+
+    /// Renew the subscription now, or subscribe again if the gateway forgot about it.
+    pub async fn renew(&self) -> Result<(), SubscribeError> {
+        renew(&self.shared).await.map(|_| ())
+    }
+
+    /// Cancel the subscription and stop receiving events.
+    pub async fn unsubscribe(self) -> Result<(), SubscribeError> {
+        let sid = self.shared.state.lock().unwrap().sid.take();
+        match sid {
+            Some(sid) => unsubscribe(&self.shared.gateway, &self.shared.event_url, sid).await,
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        let sid = self.shared.state.lock().unwrap().sid.take();
+        if let Some(sid) = sid {
+            let (gateway, event_url) = (self.shared.gateway.clone(), self.shared.event_url.clone());
+            runtime::spawn(async move {
+                let _ = unsubscribe(&gateway, &event_url, sid).await;
+            });
+        }
+    }
+}
+
+impl fmt::Debug for Subscription {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Subscription({} to {})", self.sid(), self.shared.event_url)
+    }
+}
+
+// Make a new subscription, replacing the previous one if any.
+async fn subscribe(shared: &Shared) -> Result<Option<Duration>, SubscribeError> {
+    let headers = vec![
+        ("CALLBACK", format!("<{}>", shared.callback)),
+        ("NT", "upnp:event".to_owned()),
+        ("TIMEOUT", timeout_header(shared.requested_timeout)),
+    ];
+    let (sid, timeout) = send_request(&shared.gateway, &shared.event_url, "SUBSCRIBE", headers).await?;
+    let sid = sid.ok_or(SubscribeError::InvalidResponse)?;
+    let timeout = granted_timeout(timeout.as_deref(), shared.requested_timeout);
+    *shared.state.lock().unwrap() = State { sid: Some(sid), timeout };
+    Ok(timeout)
+}
+
+// Renew the subscription, or subscribe again if the gateway does not know it anymore.
+async fn renew(shared: &Shared) -> Result<Option<Duration>, SubscribeError> {
+    let sid = shared.state.lock().unwrap().sid.clone();
+    let sid = match sid {
+        Some(sid) => sid,
+        None => return subscribe(shared).await,
+    };
+    let headers = vec![("SID", sid), ("TIMEOUT", timeout_header(shared.requested_timeout))];
+    match send_request(&shared.gateway, &shared.event_url, "SUBSCRIBE", headers).await {
+        Ok((_, timeout)) => {
+            let timeout = granted_timeout(timeout.as_deref(), shared.requested_timeout);
+            shared.state.lock().unwrap().timeout = timeout;
+            Ok(timeout)
+        }
+        // The gateway rebooted or let the subscription expire.
+        Err(SubscribeError::Refused(412)) => subscribe(shared).await,
+        Err(err) => Err(err),
+    }
+}
+
+async fn unsubscribe(gateway: &Gateway, event_url: &str, sid: String) -> Result<(), SubscribeError> {
+    send_request(gateway, event_url, "UNSUBSCRIBE", vec![("SID", sid)]).await.map(|_| ())
+}
+
+fn schedule_renewal(shared: Weak<Shared>, delay: Duration) {
+    runtime::spawn(async move {
+        let mut delay = delay;
+        loop {
+            runtime::sleep(delay).await;
+            let strong = match shared.upgrade() {
+                Some(strong) => strong,
+                // The subscription was dropped.
+                None => return,
+            };
+            delay = match renew(&strong).await {
+                Ok(Some(timeout)) => renew_delay(timeout),
+                Ok(None) => return,
+                Err(_) => RETRY_INTERVAL,
+            };
+        }
+    });
+}
+
+// Send a SUBSCRIBE or UNSUBSCRIBE request, returning the SID and TIMEOUT headers of the response.
+async fn send_request(gateway: &Gateway, url: &str, method: &str, headers: Vec<(&'static str, String)>) -> Result<(Option<String>, Option<String>), SubscribeError> {
+    let uri: Uri = url.parse()?;
+    let mut request = Request::builder().method(Method::from_bytes(method.as_bytes()).unwrap()).uri(uri);
+    for (name, value) in headers {
+        request = request.header(name, value.as_str());
+    }
+    let request = request.body(Bytes::new()).unwrap();
+
+    let response = {
+        let _turn = gateway.wait_for_turn().await;
+        gateway.transport().send(request).await?
+    };
+    if !response.status().is_success() {
+        return Err(SubscribeError::Refused(response.status().as_u16()));
+    }
+    let header = |name| response.headers().get(name).and_then(|value| value.to_str().ok()).map(|value| value.trim().to_owned());
+    Ok((header("SID"), header("TIMEOUT")))
+}
+
+// Accept the connections of the gateway on the callback address until the subscription is dropped.
+async fn serve(listener: TcpListener, events: UnboundedSender<GatewayEvent>, state: Arc<Mutex<State>>, mut shutdown: oneshot::Receiver<()>) {
+    loop {
+        let stream = match future::select(pin!(listener.accept()), &mut shutdown).await {
+            Either::Left((Ok(stream), _)) => stream,
+            Either::Left((Err(_), _)) => continue,
+            Either::Right(_) => return,
+        };
+        let events = events.clone();
+        let state = state.clone();
+        let service = service_fn(move |request| handle_notify(request, events.clone(), state.clone()));
+        let connection = http1::Builder::new().serve_connection(stream, service);
+        runtime::spawn(async move {
+            let _ = connection.await;
+        });
+    }
+}
+
+// Answer a NOTIFY request of the gateway and forward the events it carries.
+async fn handle_notify(request: Request<Incoming>, events: UnboundedSender<GatewayEvent>, state: Arc<Mutex<State>>) -> Result<Response<Full<Bytes>>, hyper::Error> {
+    if request.method().as_str() != "NOTIFY" {
+        return Ok(empty_response(StatusCode::METHOD_NOT_ALLOWED));
+    }
+    let sid = request.headers().get("SID").and_then(|sid| sid.to_str().ok()).map(|sid| sid.trim().to_owned());
+    let known = match (sid, &state.lock().unwrap().sid) {
+        (Some(sid), Some(expected)) => sid == *expected,
+        // The first event can arrive before the response to the subscription.
+        (Some(_), None) => true,
+        (None, _) => false,
+    };
+    if !known {
+        return Ok(empty_response(StatusCode::PRECONDITION_FAILED));
+    }
+
+    let body = request.into_body().collect().await?.to_bytes();
+    match parse_property_set(&body) {
+        Some(parsed) => {
+            for event in parsed {
+                let _ = events.unbounded_send(event);
+            }
+            Ok(empty_response(StatusCode::OK))
+        }
+        None => Ok(empty_response(StatusCode::BAD_REQUEST)),
+    }
+}
+
+fn empty_response(status: StatusCode) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(Bytes::new()));
+    *response.status_mut() = status;
+    response
+}
+
+// Parse the state variables listed in the body of a NOTIFY request.
+fn parse_property_set(body: &[u8]) -> Option<Vec<GatewayEvent>> {
+    let property_set = xmltree::Element::parse(body).ok()?;
+    if property_set.name != "propertyset" {
+        return None;
+    }
+    let events = property_set
+        .children
+        .iter()
+        .filter(|property| property.name == "property")
+        .flat_map(|property| property.children.iter())
+        .map(|variable| GatewayEvent::parse(&variable.name, variable.text.as_ref().map(|t| t.trim()).unwrap_or("")))
+        .collect();
+    Some(events)
+}
+
+fn timeout_header(timeout: Duration) -> String {
+    format!("Second-{}", timeout.as_secs())
+}
+
+// The duration granted by the gateway, None if infinite. Gateways that do not tell grant the one asked for.
+fn granted_timeout(header: Option<&str>, requested: Duration) -> Option<Duration> {
+    let header = match header {
+        Some(header) => header.trim(),
+        None => return Some(requested),
+    };
+    // GENA sends `Second-infinite`, some gateways a bare `infinite`.
+    let value = header.get(..7).filter(|prefix| prefix.eq_ignore_ascii_case("Second-")).map_or(header, |_| &header[7..]);
+    if value.eq_ignore_ascii_case("infinite") {
+        return None;
+    }
+    Some(value.parse().map_or(requested, Duration::from_secs))
+}
+
+// Renew at half of the subscription.
+fn renew_delay(timeout: Duration) -> Duration {
+    (timeout / 2).max(Duration::from_secs(1))
+}
+
+// The local address the gateway is reached from, with the scope id of link-local addresses and
+// port 0. Connecting a UDP socket sends nothing.
+fn local_addr_towards(addr: SocketAddr) -> io::Result<SocketAddr> {
+    let unspecified: IpAddr = match addr {
+        SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    };
+    let socket = StdUdpSocket::bind(SocketAddr::new(unspecified, 0))?;
+    socket.connect(addr)?;
+    let mut local_addr = socket.local_addr()?;
+    local_addr.set_port(0);
+    Ok(local_addr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_property_set() {
+        let body = r#"<?xml version="1.0"?>
+<e:propertyset xmlns:e="urn:schemas-upnp-org:event-1-0">
+  <e:property><ExternalIPAddress>203.0.113.7</ExternalIPAddress></e:property>
+  <e:property><ConnectionStatus>Connected</ConnectionStatus></e:property>
+  <e:property><PortMappingNumberOfEntries>3</PortMappingNumberOfEntries></e:property>
+  <e:property><ExternalIPAddress></ExternalIPAddress></e:property>
+</e:propertyset>"#;
+        let events = parse_property_set(body.as_bytes()).unwrap();
+        assert_eq!(
+            events,
+            vec![
+                GatewayEvent::ExternalIpAddress(Ipv4Addr::new(203, 0, 113, 7)),
+                GatewayEvent::ConnectionStatus(ConnectionStatus::Connected),
+                GatewayEvent::PortMappingNumberOfEntries(3),
+                GatewayEvent::Other {
+                    name: "ExternalIPAddress".to_owned(),
+                    value: "".to_owned(),
+                },
+            ]
+        );
+        assert!(parse_property_set(b"<root/>").is_none());
+    }
+
+    #[test]
+    fn test_granted_timeout() {
+        let requested = Duration::from_secs(1800);
+        assert_eq!(granted_timeout(Some("Second-300"), requested), Some(Duration::from_secs(300)));
+        assert_eq!(granted_timeout(Some("second-300"), requested), Some(Duration::from_secs(300)));
+        assert_eq!(granted_timeout(Some("infinite"), requested), None);
+        assert_eq!(granted_timeout(Some("Second-infinite"), requested), None);
+        assert_eq!(granted_timeout(Some("second-INFINITE"), requested), None);
+        assert_eq!(granted_timeout(Some("garbage"), requested), Some(requested));
+        assert_eq!(granted_timeout(None, requested), Some(requested));
+    }
+}
@@ -0,0 +1,260 @@
+use std::net::SocketAddrV6;
+use std::time::Duration;
+
+use crate::errors::{PinholeError, RequestError};
+use crate::gateway::{child_text, parse_bool, Idempotent};
+use crate::{Gateway, PortMappingProtocol};
+
+const SERVICE_TYPE: &str = "urn:schemas-upnp-org:service:WANIPv6FirewallControl:1";
+
+impl Gateway {
+    // Call the given action of the WANIPv6FirewallControl service with the given arguments.
+    async fn perform_firewall_action(&self, action: &str, args: &str, idempotent: Idempotent) -> Result<(String, xmltree::Element), PinholeError> {
+        let control_url = self.firewall_control_url().ok_or(PinholeError::ServiceNotAvailable)?;
+        Ok(self.perform_service_action(&control_url, SERVICE_TYPE, action, args, idempotent).await?)
+    }
+
+    /// Get whether the IPv6 firewall of the gateway is enabled and whether inbound pinholes can be opened.
+    pub async fn firewall_status(&self) -> Result<FirewallStatus, PinholeError> {
+        let (text, response) = self.perform_firewall_action("GetFirewallStatus", "", Idempotent::Yes).await?;
+        let status = (|| {
+            Some(FirewallStatus {
+                firewall_enabled: parse_bool(child_text(&response, "FirewallEnabled")?)?,
+                inbound_pinhole_allowed: parse_bool(child_text(&response, "InboundPinholeAllowed")?)?,
+            })
+        })();
+        status.ok_or(PinholeError::RequestError(RequestError::InvalidResponse(text)))
+    }
+
+    /// Get how long the gateway keeps an outbound pinhole open without traffic.
+    ///
+    /// The remote_addr is the address of the remote peer, the internal_addr the address of the
+    /// local client. An unspecified ip or a port 0 stands for any.
+    pub async fn outbound_pinhole_timeout(&self, protocol: PortMappingProtocol, remote_addr: SocketAddrV6, internal_addr: SocketAddrV6) -> Result<Duration, PinholeError> {
+        let args = format!("{}<Protocol>{}</Protocol>", pinhole_addresses(remote_addr, internal_addr), protocol_number(protocol));
+        let (text, response) = self.perform_firewall_action("GetOutboundPinholeTimeout", &args, Idempotent::Yes).await?;
+        match child_text(&response, "OutboundPinholeTimeout").and_then(|t| t.parse::<u64>().ok()) {
+            Some(timeout) => Ok(Duration::from_secs(timeout)),
+            None => Err(PinholeError::RequestError(RequestError::InvalidResponse(text))),
+        }
+    }
+
+    /// Open an inbound pinhole in the IPv6 firewall of the gateway.
+    ///
+    /// The remote_addr is the address of the remote peer allowed through, the internal_addr the
+    /// address the traffic is sent to. An unspecified ip or a port 0 stands for any.
+    /// The lease_time parameter is in seconds, from 1 to 86400.
+    ///
+    /// # Returns
+    ///
+    /// The identifier of the pinhole on success. Otherwise an error.
+    pub async fn add_pinhole(&self, protocol: PortMappingProtocol, remote_addr: SocketAddrV6, internal_addr: SocketAddrV6, lease_time: u32) -> Result<u16, PinholeError> {
+        let args = format!(
+            "{}<Protocol>{}</Protocol>
+                <LeaseTime>{}</LeaseTime>",
+            pinhole_addresses(remote_addr, internal_addr),
+            protocol_number(protocol),
+            lease_time
+        );
+        let (text, response) = self.perform_firewall_action("AddPinhole", &args, Idempotent::No).await?;
+        match child_text(&response, "UniqueID").and_then(|t| t.parse::<u16>().ok()) {
+            Some(unique_id) => Ok(unique_id),
+            None => Err(PinholeError::RequestError(RequestError::InvalidResponse(text))),
+        }
+    }
+
+    /// Extend the lease of a pinhole.
+    ///
+    /// The lease_time parameter is in seconds, from 1 to 86400.
+    pub async fn update_pinhole(&self, unique_id: u16, lease_time: u32) -> Result<(), PinholeError> {
+        let args = format!(
+            "<UniqueID>{}</UniqueID>
+                <NewLeaseTime>{}</NewLeaseTime>",
+            unique_id, lease_time
+        );
+        self.perform_firewall_action("UpdatePinhole", &args, Idempotent::Yes).await.map(|_| ())
+    }
+
+    /// Close a pinhole.
+    pub async fn delete_pinhole(&self, unique_id: u16) -> Result<(), PinholeError> {
+        let args = format!("<UniqueID>{}</UniqueID>", unique_id);
+        self.perform_firewall_action("DeletePinhole", &args, Idempotent::No).await.map(|_| ())
+    }
+
+    /// Check whether traffic goes through a pinhole.
+    ///
+    /// Fails with `PinholeError::NoTrafficReceived` when the gateway has not seen any traffic yet.
+    pub async fn check_pinhole_working(&self, unique_id: u16) -> Result<bool, PinholeError> {
+        let args = format!("<UniqueID>{}</UniqueID>", unique_id);
+        let (text, response) = self.perform_firewall_action("CheckPinholeWorking", &args, Idempotent::Yes).await?;
+        match child_text(&response, "IsWorking").and_then(parse_bool) {
+            Some(is_working) => Ok(is_working),
+            None => Err(PinholeError::RequestError(RequestError::InvalidResponse(text))),
+        }
+    }
+
+    /// Get the number of packets that went through a pinhole.
+    pub async fn pinhole_packets(&self, unique_id: u16) -> Result<u32, PinholeError> {
+        let args = format!("<UniqueID>{}</UniqueID>", unique_id);
+        let (text, response) = self.perform_firewall_action("GetPinholePackets", &args, Idempotent::Yes).await?;
+        match child_text(&response, "PinholePackets").and_then(|t| t.parse::<u32>().ok()) {
+            Some(packets) => Ok(packets),
+            None => Err(PinholeError::RequestError(RequestError::InvalidResponse(text))),
+        }
+    }
+}
+
+/// The status of the IPv6 firewall of a gateway, as returned by `Gateway::firewall_status`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FirewallStatus {
+    /// Whether the firewall is enabled. All inbound traffic is allowed when it is not.
+    pub firewall_enabled: bool,
+    /// Whether inbound pinholes can be opened.
+    pub inbound_pinhole_allowed: bool,
+}
+
+// The arguments describing the two ends of a pinhole. Unspecified values are wildcards.
+fn pinhole_addresses(remote_addr: SocketAddrV6, internal_addr: SocketAddrV6) -> String {
+    format!(
+        "<RemoteHost>{}</RemoteHost>
+                <RemotePort>{}</RemotePort>
+                <InternalClient>{}</InternalClient>
+                <InternalPort>{}</InternalPort>
+                ",
+        wildcard_host(&remote_addr),
+        remote_addr.port(),
+        wildcard_host(&internal_addr),
+        internal_addr.port()
+    )
+}
+
+fn wildcard_host(addr: &SocketAddrV6) -> String {
+    if addr.ip().is_unspecified() {
+        String::new()
+    } else {
+        addr.ip().to_string()
+    }
+}
+
+// The firewall service identifies protocols by their IANA number.
+pub(crate) fn protocol_number(protocol: PortMappingProtocol) -> u16 {
+    match protocol {
+        PortMappingProtocol::TCP => 6,
+        PortMappingProtocol::UDP => 17,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime;
+    use crate::transport::test::{envelope, fault, FnTransport};
+    use std::sync::{Arc, Mutex};
+
+    // A gateway with the firewall service, answering every action with the given output arguments.
+    fn gateway_answering(args: &'static str) -> Gateway {
+        let transport = FnTransport::new(move |action, _| Ok(envelope(action, args)));
+        Gateway::new("192.168.1.1:5000".parse().unwrap(), "/ctl/IPConn".to_owned(), "urn:schemas-upnp-org:service:WANIPConnection:2".to_owned())
+            .with_firewall_control("/ctl/IP6FCtl".to_owned())
+            .with_transport(transport)
+    }
+
+    #[test]
+    fn test_add_pinhole_arguments() {
+        let body = Arc::new(Mutex::new(String::new()));
+        let request_body = body.clone();
+        let transport = FnTransport::new(move |action, request| {
+            assert_eq!(request.uri(), "http://192.168.1.1:5000/ctl/IP6FCtl");
+            *request_body.lock().unwrap() = String::from_utf8_lossy(request.body()).split_whitespace().collect();
+            Ok(envelope(action, "<UniqueID>42</UniqueID>"))
+        });
+        let gateway = Gateway::new("192.168.1.1:5000".parse().unwrap(), "/ctl/IPConn".to_owned(), "urn:schemas-upnp-org:service:WANIPConnection:2".to_owned())
+            .with_firewall_control("/ctl/IP6FCtl".to_owned())
+            .with_transport(transport.clone());
+
+        // The unspecified address is sent as an empty host, the port 0 as it is.
+        let remote_addr = "[::]:0".parse().unwrap();
+        let internal_addr = "[2001:db8::20]:8080".parse().unwrap();
+        let unique_id = runtime::block_on(gateway.add_pinhole(PortMappingProtocol::TCP, remote_addr, internal_addr, 3600)).unwrap();
+        assert_eq!(unique_id, 42);
+        assert!(body.lock().unwrap().contains(
+            "<RemoteHost></RemoteHost><RemotePort>0</RemotePort><InternalClient>2001:db8::20</InternalClient><InternalPort>8080</InternalPort>\
+             <Protocol>6</Protocol><LeaseTime>3600</LeaseTime>"
+        ));
+
+        let remote_addr = "[2001:db8::1]:5000".parse().unwrap();
+        let internal_addr = "[::]:0".parse().unwrap();
+        runtime::block_on(gateway.add_pinhole(PortMappingProtocol::UDP, remote_addr, internal_addr, 60)).unwrap();
+        assert!(body.lock().unwrap().contains(
+            "<RemoteHost>2001:db8::1</RemoteHost><RemotePort>5000</RemotePort><InternalClient></InternalClient><InternalPort>0</InternalPort>\
+             <Protocol>17</Protocol><LeaseTime>60</LeaseTime>"
+        ));
+        assert_eq!(transport.actions(), ["AddPinhole", "AddPinhole"]);
+    }
+
+    #[test]
+    fn test_parse_pinhole_responses() {
+        let any = "[::]:0".parse().unwrap();
+
+        let status = runtime::block_on(gateway_answering("<FirewallEnabled>1</FirewallEnabled><InboundPinholeAllowed>0</InboundPinholeAllowed>").firewall_status()).unwrap();
+        assert_eq!(
+            status,
+            FirewallStatus {
+                firewall_enabled: true,
+                inbound_pinhole_allowed: false,
+            }
+        );
+        let timeout = runtime::block_on(gateway_answering("<OutboundPinholeTimeout>300</OutboundPinholeTimeout>").outbound_pinhole_timeout(PortMappingProtocol::UDP, any, any)).unwrap();
+        assert_eq!(timeout, Duration::from_secs(300));
+        assert!(runtime::block_on(gateway_answering("<IsWorking>1</IsWorking>").check_pinhole_working(42)).unwrap());
+        assert!(!runtime::block_on(gateway_answering("<IsWorking>0</IsWorking>").check_pinhole_working(42)).unwrap());
+        assert_eq!(runtime::block_on(gateway_answering("<PinholePackets>1234</PinholePackets>").pinhole_packets(42)).unwrap(), 1234);
+        runtime::block_on(gateway_answering("").update_pinhole(42, 3600)).unwrap();
+        runtime::block_on(gateway_answering("").delete_pinhole(42)).unwrap();
+
+        // Missing or malformed values.
+        let is_invalid = |result: Result<(), PinholeError>| matches!(result, Err(PinholeError::RequestError(RequestError::InvalidResponse(_))));
+        assert!(is_invalid(runtime::block_on(gateway_answering("<FirewallEnabled>1</FirewallEnabled>").firewall_status()).map(|_| ())));
+        assert!(is_invalid(runtime::block_on(gateway_answering("<OutboundPinholeTimeout>-1</OutboundPinholeTimeout>").outbound_pinhole_timeout(PortMappingProtocol::TCP, any, any)).map(|_| ())));
+        assert!(is_invalid(runtime::block_on(gateway_answering("<UniqueID>70000</UniqueID>").add_pinhole(PortMappingProtocol::TCP, any, any, 60)).map(|_| ())));
+        assert!(is_invalid(runtime::block_on(gateway_answering("<IsWorking>maybe</IsWorking>").check_pinhole_working(42)).map(|_| ())));
+        assert!(is_invalid(runtime::block_on(gateway_answering("").pinhole_packets(42)).map(|_| ())));
+    }
+
+    #[test]
+    fn test_pinhole_error_codes() {
+        let cases = [
+            (606, PinholeError::ActionNotAuthorized),
+            (701, PinholeError::PinholeSpaceExhausted),
+            (702, PinholeError::FirewallDisabled),
+            (703, PinholeError::InboundPinholeNotAllowed),
+            (704, PinholeError::NoSuchEntry),
+            (705, PinholeError::ProtocolNotSupported),
+            (706, PinholeError::InternalPortWildcardingNotAllowed),
+            (707, PinholeError::ProtocolWildcardingNotAllowed),
+            (708, PinholeError::WildCardNotPermittedInSrcIp),
+            (709, PinholeError::NoTrafficReceived),
+            (501, PinholeError::RequestError(RequestError::ErrorCode(501, "Refused".to_owned()))),
+        ];
+        for (code, expected) in cases {
+            let transport = FnTransport::new(move |_, _| Ok(fault(code)));
+            let gateway = Gateway::new("192.168.1.1:5000".parse().unwrap(), "/ctl/IPConn".to_owned(), "urn:schemas-upnp-org:service:WANIPConnection:2".to_owned())
+                .with_firewall_control("/ctl/IP6FCtl".to_owned())
+                .with_transport(transport);
+            match runtime::block_on(gateway.check_pinhole_working(42)) {
+                Err(ref e) if std::mem::discriminant(e) == std::mem::discriminant(&expected) => (),
+                other => panic!("unexpected result for {}: {:?}", code, other),
+            }
+        }
+
+        // Without the service, no request is sent.
+        let transport = FnTransport::new(|_, _| Ok(fault(501)));
+        let gateway = Gateway::new("192.168.1.1:5000".parse().unwrap(), "/ctl/IPConn".to_owned(), "urn:schemas-upnp-org:service:WANIPConnection:2".to_owned())
+            .with_transport(transport.clone());
+        match runtime::block_on(gateway.delete_pinhole(42)) {
+            Err(PinholeError::ServiceNotAvailable) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(transport.actions().is_empty());
+    }
+}
@@ -1,40 +1,138 @@
 use rand::distributions::IndependentSample;
 use std::fmt;
 use std::hash::{Hash, Hasher};
-use std::net::{IpAddr, Ipv4Addr, SocketAddrV4};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use crate::errors::{AddAnyPortError, AddPortError, GetExternalIpError, RemovePortError, RequestError};
-use futures::future;
-use futures::Future;
-use rand;
+use crate::errors::{AddAnyPortError, AddPortError, GetConnectionInfoError, GetExternalIpError, GetGenericPortMappingEntryError, GetPortMappingError, GetStatusInfoError, ListPortMappingsError, RemovePortError, RequestError};
+use async_lock::{Semaphore, SemaphoreGuardArc};
+use futures::stream::{self, BoxStream};
 use crate::soap;
-use tokio_retry::strategy::FixedInterval;
-use tokio_retry::{Error as RetryError, RetryIf};
-use xmltree;
+use crate::transport::{default_transport, Transport};
 
-use crate::PortMappingProtocol;
+use crate::{PortMappingProtocol, RequestPolicy};
 
 /// This structure represents a gateway found by the search functions.
 #[derive(Clone, Debug)]
 pub struct Gateway {
     /// Socket address of the gateway
-    addr: SocketAddrV4,
+    addr: SocketAddr,
     /// Control url of the device
     control_url: String,
+    /// Type of the connection service at the control url
+    service_type: String,
+    /// Control url of the WANCommonInterfaceConfig service of the device, if any
+    common_interface_control_url: Option<String>,
+    /// Control url of the WANIPv6FirewallControl service of the device, if any
+    firewall_control_url: Option<String>,
+    /// Event subscription url of the connection service, if any
+    event_sub_url: Option<String>,
+    /// Value of the BOOTID.UPNP.ORG header of the search response, if any
+    boot_id: Option<u32>,
+    /// HTTP client the requests are sent with
+    transport: Arc<dyn Transport>,
+    /// Timeouts and retries of the requests
+    policy: RequestPolicy,
+    /// How many requests may be sent at the same time, shared by the clones
+    in_flight: Arc<RequestLimit>,
 }
 
+// The limit on the requests in flight to a gateway, with the queue of the requests waiting for
+// their turn. None when unlimited.
+#[derive(Debug, Default)]
+struct RequestLimit(Mutex<Option<(usize, Arc<Semaphore>)>>);
+
 impl Gateway {
     /// Create a new Gateway
-    pub fn new(addr: SocketAddrV4, control_url: String) -> Gateway {
+    ///
+    /// The service type is the URN of the connection service at the control url, for instance
+    /// `urn:schemas-upnp-org:service:WANIPConnection:1` or `urn:schemas-upnp-org:service:WANPPPConnection:1`.
+    pub fn new(addr: SocketAddr, control_url: String, service_type: String) -> Gateway {
         Gateway {
             addr,
             control_url,
+            service_type,
+            common_interface_control_url: None,
+            firewall_control_url: None,
+            event_sub_url: None,
+            boot_id: None,
+            transport: default_transport(),
+            policy: RequestPolicy::default(),
+            in_flight: Default::default(),
         }
     }
 
+    /// Set the control url of the WANCommonInterfaceConfig service of the gateway.
+    ///
+    /// The search functions set it when the description of the device lists the service.
+    pub fn with_common_interface_config(mut self, control_url: String) -> Gateway {
+        self.common_interface_control_url = Some(control_url);
+        self
+    }
+
+    /// Set the control url of the WANIPv6FirewallControl service of the gateway.
+    ///
+    /// The search functions set it when the description of the device lists the service.
+    pub fn with_firewall_control(mut self, control_url: String) -> Gateway {
+        self.firewall_control_url = Some(control_url);
+        self
+    }
+
+    /// Set the event subscription url of the connection service of the gateway.
+    ///
+    /// The search functions set it when the description of the device lists one.
+    pub fn with_event_sub_url(mut self, event_sub_url: String) -> Gateway {
+        self.event_sub_url = Some(event_sub_url);
+        self
+    }
+
+    /// Set the HTTP client the requests to the gateway are sent with, `HyperTransport` by default.
+    ///
+    /// The search functions set it to the transport they were given.
+    pub fn with_transport(mut self, transport: Arc<dyn Transport>) -> Gateway {
+        self.transport = transport;
+        self
+    }
+
+    /// Set the timeouts and retries of the requests to the gateway, `RequestPolicy::default()` by default.
+    ///
+    /// To change them for a single call, make it on a copy of the gateway:
+    /// `gateway.clone().with_request_policy(policy)`.
+    pub fn with_request_policy(mut self, policy: RequestPolicy) -> Gateway {
+        self.policy = policy;
+        self
+    }
+
+    /// Set how many requests may be sent to the gateway at the same time, unlimited by default.
+    ///
+    /// The other requests wait in turn, which spares the cheap gateways that lock up under load.
+    /// The SOAP actions and the event subscriptions count toward the limit. The description of the
+    /// gateway is read once by the search, before the limit can be set.
+    ///
+    /// The limit is shared by the gateway and all its clones, including the ones made before. The
+    /// requests already in flight or waiting when it changes are not counted toward the new limit.
+    /// A limit of 0 is taken as 1.
+    pub fn with_max_in_flight_requests(self, limit: usize) -> Gateway {
+        let limit = limit.max(1);
+        *self.in_flight.0.lock().unwrap() = Some((limit, Arc::new(Semaphore::new(limit))));
+        self
+    }
+
+    // Set the boot id the gateway announced when it answered the search.
+    pub(crate) fn with_boot_id(mut self, boot_id: Option<u32>) -> Gateway {
+        self.boot_id = boot_id;
+        self
+    }
+
     /// get ip addr of the gateway
     pub fn ip_addr(&self) -> IpAddr {
-        (*self.addr.ip()).into()
+        self.addr.ip()
+    }
+
+    // The address of the HTTP server of the gateway.
+    pub(crate) fn addr(&self) -> SocketAddr {
+        self.addr
     }
 
     /// get control_url of the gateway
@@ -42,34 +140,166 @@ impl Gateway {
         self.control_url.clone()
     }
 
-    fn perform_request(&self, header: &str, body: &str, ok: &str) -> Box<Future<Item = (String, xmltree::Element), Error = RequestError>> {
-        let url = format!("{}", self);
-        let ok = ok.to_owned();
-        let future = soap::send_async(&url, header, body)
-            .map_err(|err| RequestError::from(err))
-            .and_then(move |text| parse_response(text, &ok));
-        Box::new(future)
-    }
-
-    /// Get the external IP address of the gateway in a tokio compatible way
-    pub fn get_external_ip(&self) -> Box<Future<Item = Ipv4Addr, Error = GetExternalIpError>> {
-        let header = "\"urn:schemas-upnp-org:service:WANIPConnection:1#GetExternalIPAddress\"";
-        let body = "<?xml version=\"1.0\"?>
-        <SOAP-ENV:Envelope SOAP-ENV:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\" xmlns:SOAP-ENV=\"http://schemas.xmlsoap.org/soap/envelope/\">
-            <SOAP-ENV:Body>
-                <m:GetExternalIPAddress xmlns:m=\"urn:schemas-upnp-org:service:WANIPConnection:1\">
-                </m:GetExternalIPAddress>
-            </SOAP-ENV:Body>
-        </SOAP-ENV:Envelope>";
-        let future = self.perform_request(header, body, "GetExternalIPAddressResponse").then(|result| match result {
+    /// get the service type of the connection service of the gateway
+    pub fn service_type(&self) -> String {
+        self.service_type.clone()
+    }
+
+    /// get the version of the connection service of the gateway
+    pub fn version(&self) -> u32 {
+        self.service_type.rsplit(':').next().and_then(|v| v.parse().ok()).unwrap_or(1)
+    }
+
+    /// get the control url of the WANCommonInterfaceConfig service of the gateway, if any
+    pub fn common_interface_control_url(&self) -> Option<String> {
+        self.common_interface_control_url.clone()
+    }
+
+    /// get the control url of the WANIPv6FirewallControl service of the gateway, if any
+    pub fn firewall_control_url(&self) -> Option<String> {
+        self.firewall_control_url.clone()
+    }
+
+    /// get the event subscription url of the connection service of the gateway, if any
+    pub fn event_sub_url(&self) -> Option<String> {
+        self.event_sub_url.clone()
+    }
+
+    /// get the boot id the gateway announced when it was found, if any
+    ///
+    /// UPnP 1.1 devices change their boot id every time they reboot. Gateways implementing older
+    /// versions of the specification do not announce one.
+    pub fn boot_id(&self) -> Option<u32> {
+        self.boot_id
+    }
+
+    /// get the HTTP client the requests to the gateway are sent with
+    pub fn transport(&self) -> Arc<dyn Transport> {
+        self.transport.clone()
+    }
+
+    /// get the timeouts and retries of the requests to the gateway
+    pub fn request_policy(&self) -> &RequestPolicy {
+        &self.policy
+    }
+
+    /// get how many requests may be sent to the gateway at the same time, None if unlimited
+    pub fn max_in_flight_requests(&self) -> Option<usize> {
+        self.in_flight.0.lock().unwrap().as_ref().map(|(limit, _)| *limit)
+    }
+
+    // Wait until a request may be sent to the gateway. It counts as in flight until the guard is
+    // dropped.
+    pub(crate) async fn wait_for_turn(&self) -> Option<SemaphoreGuardArc> {
+        let semaphore = self.in_flight.0.lock().unwrap().as_ref().map(|(_, semaphore)| semaphore.clone());
+        match semaphore {
+            Some(semaphore) => Some(semaphore.acquire_arc().await),
+            None => None,
+        }
+    }
+
+    async fn perform_request(&self, control_url: &str, header: &str, body: &str, ok: &str, idempotent: Idempotent) -> Result<(String, xmltree::Element), RequestError> {
+        let url = format!("http://{}{}", self.addr, control_url);
+        let _turn = self.wait_for_turn().await;
+        let text = soap::send_async(&*self.transport, &self.policy, &url, header, body, idempotent == Idempotent::Yes).await?;
+        parse_response(text, ok)
+    }
+
+    // Call the given action of the connection service with the given arguments.
+    async fn perform_action(&self, action: &str, args: &str, idempotent: Idempotent) -> Result<(String, xmltree::Element), RequestError> {
+        self.perform_service_action(&self.control_url, &self.service_type, action, args, idempotent).await
+    }
+
+    // Call the given action of the service at the given control url with the given arguments.
+    pub(crate) async fn perform_service_action(&self, control_url: &str, service_type: &str, action: &str, args: &str, idempotent: Idempotent) -> Result<(String, xmltree::Element), RequestError> {
+        let header = format!("\"{}#{}\"", service_type, action);
+        let body = format!(
+            "<?xml version=\"1.0\"?>
+        <s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">
+        <s:Body>
+            <u:{action} xmlns:u=\"{service_type}\">
+                {args}
+            </u:{action}>
+        </s:Body>
+        </s:Envelope>
+        ",
+            action = action,
+            service_type = service_type,
+            args = args
+        );
+        self.perform_request(control_url, &header, &body, &format!("{}Response", action), idempotent).await
+    }
+
+    /// Get the external IP address of the gateway.
+    pub async fn get_external_ip(&self) -> Result<Ipv4Addr, GetExternalIpError> {
+        match self.perform_action("GetExternalIPAddress", "", Idempotent::Yes).await {
             Ok((text, response)) => match response.get_child("NewExternalIPAddress").and_then(|e| e.text.as_ref()).and_then(|t| t.parse::<Ipv4Addr>().ok()) {
                 Some(ipv4_addr) => Ok(ipv4_addr),
                 None => Err(GetExternalIpError::RequestError(RequestError::InvalidResponse(text))),
             },
             Err(RequestError::ErrorCode(606, _)) => Err(GetExternalIpError::ActionNotAuthorized),
             Err(e) => Err(GetExternalIpError::RequestError(e)),
-        });
-        Box::new(future)
+        }
+    }
+
+    /// Get the status of the connection of the gateway, the reason of the last disconnection and the uptime.
+    pub async fn status_info(&self) -> Result<StatusInfo, GetStatusInfoError> {
+        match self.perform_action("GetStatusInfo", "", Idempotent::Yes).await {
+            Ok((text, response)) => {
+                let status_info = (|| {
+                    Some(StatusInfo {
+                        connection_status: ConnectionStatus::parse(child_text(&response, "NewConnectionStatus")?),
+                        last_connection_error: LastConnectionError::parse(child_text(&response, "NewLastConnectionError").unwrap_or("ERROR_NONE")),
+                        uptime: Duration::from_secs(child_text(&response, "NewUptime")?.parse().ok()?),
+                    })
+                })();
+                status_info.ok_or(GetStatusInfoError::RequestError(RequestError::InvalidResponse(text)))
+            }
+            Err(RequestError::ErrorCode(606, _)) => Err(GetStatusInfoError::ActionNotAuthorized),
+            Err(e) => Err(GetStatusInfoError::RequestError(e)),
+        }
+    }
+
+    /// Get whether the gateway supports RSIP and whether NAT is enabled.
+    ///
+    /// A gateway with NAT disabled, such as a router in bridge mode, does not translate
+    /// addresses. Port mappings have no effect there.
+    pub async fn nat_rsip_status(&self) -> Result<NatRsipStatus, GetConnectionInfoError> {
+        let (text, response) = self.perform_action("GetNATRSIPStatus", "", Idempotent::Yes).await?;
+        let status = (|| {
+            Some(NatRsipStatus {
+                rsip_available: parse_bool(child_text(&response, "NewRSIPAvailable")?)?,
+                nat_enabled: parse_bool(child_text(&response, "NewNATEnabled")?)?,
+            })
+        })();
+        status.ok_or(GetConnectionInfoError::RequestError(RequestError::InvalidResponse(text)))
+    }
+
+    /// Get the type of the connection of the gateway and the types it could be set to.
+    pub async fn connection_type_info(&self) -> Result<ConnectionTypeInfo, GetConnectionInfoError> {
+        let (text, response) = self.perform_action("GetConnectionTypeInfo", "", Idempotent::Yes).await?;
+        match child_text(&response, "NewConnectionType") {
+            Some(connection_type) => Ok(ConnectionTypeInfo {
+                connection_type: ConnectionType::parse(connection_type),
+                possible_connection_types: child_text(&response, "NewPossibleConnectionTypes")
+                    .unwrap_or("")
+                    .split(',')
+                    .map(|t| t.trim())
+                    .filter(|t| !t.is_empty())
+                    .map(ConnectionType::parse)
+                    .collect(),
+            }),
+            None => Err(GetConnectionInfoError::RequestError(RequestError::InvalidResponse(text))),
+        }
+    }
+
+    /// Get the number of entries in the port mapping table of the gateway.
+    pub async fn port_mapping_number_of_entries(&self) -> Result<u32, GetConnectionInfoError> {
+        let (text, response) = self.perform_action("GetPortMappingNumberOfEntries", "", Idempotent::Yes).await?;
+        match child_text(&response, "NewPortMappingNumberOfEntries").and_then(|t| t.parse::<u32>().ok()) {
+            Some(entries) => Ok(entries),
+            None => Err(GetConnectionInfoError::RequestError(RequestError::InvalidResponse(text))),
+        }
     }
 
     /// Get an external socket address with our external ip and any port. This is a convenience
@@ -81,21 +311,13 @@ impl Gateway {
     /// # Returns
     ///
     /// The external address that was mapped on success. Otherwise an error.
-    pub fn get_any_address(&self, protocol: PortMappingProtocol, local_addr: SocketAddrV4, lease_duration: u32, description: &str) -> Box<Future<Item = SocketAddrV4, Error = AddAnyPortError>> {
-        let description = description.to_owned();
-        let gateway = self.clone();
-        let future = self
-            .get_external_ip()
-            .map_err(|err| match err {
-                GetExternalIpError::ActionNotAuthorized => AddAnyPortError::ActionNotAuthorized,
-                GetExternalIpError::RequestError(e) => AddAnyPortError::RequestError(e),
-            })
-            .and_then(move |ip| {
-                gateway
-                    .add_any_port(protocol, local_addr, lease_duration, &description)
-                    .and_then(move |port| Ok(SocketAddrV4::new(ip, port)))
-            });
-        Box::new(future)
+    pub async fn get_any_address(&self, protocol: PortMappingProtocol, local_addr: SocketAddrV4, lease_duration: u32, description: &str) -> Result<SocketAddrV4, AddAnyPortError> {
+        let ip = self.get_external_ip().await.map_err(|err| match err {
+            GetExternalIpError::ActionNotAuthorized => AddAnyPortError::ActionNotAuthorized,
+            GetExternalIpError::RequestError(e) => AddAnyPortError::RequestError(e),
+        })?;
+        let port = self.add_any_port(protocol, local_addr, lease_duration, description).await?;
+        Ok(SocketAddrV4::new(ip, port))
     }
 
     /// Add a port mapping.with any external port.
@@ -106,39 +328,36 @@ impl Gateway {
     /// # Returns
     ///
     /// The external port that was mapped on success. Otherwise an error.
-    pub fn add_any_port(&self, protocol: PortMappingProtocol, local_addr: SocketAddrV4, lease_duration: u32, description: &str) -> Box<Future<Item = u16, Error = AddAnyPortError>> {
+    ///
+    /// The mapping stays on the gateway until removed or its lease expires, see
+    /// `add_any_port_guarded` for a mapping removed when its handle is dropped.
+    pub async fn add_any_port(&self, protocol: PortMappingProtocol, local_addr: SocketAddrV4, lease_duration: u32, description: &str) -> Result<u16, AddAnyPortError> {
         // This function first attempts to call AddAnyPortMapping on the IGD with a random port
-        // number. If that fails due to the method being unknown it attempts to call AddPortMapping
-        // instead with a random port number. If that fails due to ConflictInMappingEntry it retrys
-        // with another port up to a maximum of 20 times. If it fails due to SamePortValuesRequired
-        // it retrys once with the same port values.
+        // number. If that fails due to the method being unknown, or if the IGD only offers version
+        // 1 of the service which has no such method, it attempts to call AddPortMapping instead
+        // with a random port number. If that fails due to ConflictInMappingEntry it retries with
+        // another port, up to the random_port_attempts of the request policy. If it fails due to
+        // SamePortValuesRequired it retries once with the same port values.
 
         if local_addr.port() == 0 {
-            return Box::new(future::err(AddAnyPortError::InternalPortZeroInvalid));
+            return Err(AddAnyPortError::InternalPortZeroInvalid);
         }
 
-        let port_range = rand::distributions::Range::new(32_768_u16, 65_535_u16);
-        let mut rng = rand::thread_rng();
-        let external_port = port_range.ind_sample(&mut rng);
+        if self.version() < 2 {
+            return self.retry_add_random_port_mapping(protocol, local_addr, lease_duration, description).await;
+        }
 
-        let header = "\"urn:schemas-upnp-org:service:WANIPConnection:1#AddAnyPortMapping\"";
-        let body = format!(
-            "<?xml version=\"1.0\"?>
-        <s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">
-        <s:Body>
-            <u:AddAnyPortMapping xmlns:u=\"urn:schemas-upnp-org:service:WANIPConnection:1\">
-                <NewProtocol>{}</NewProtocol>
+        let external_port = random_port();
+
+        let args = format!(
+            "<NewProtocol>{}</NewProtocol>
                 <NewExternalPort>{}</NewExternalPort>
                 <NewInternalClient>{}</NewInternalClient>
                 <NewInternalPort>{}</NewInternalPort>
                 <NewLeaseDuration>{}</NewLeaseDuration>
                 <NewPortMappingDescription>{}</NewPortMappingDescription>
                 <NewEnabled>1</NewEnabled>
-                <NewRemoteHost></NewRemoteHost>
-            </u:AddAnyPortMapping>
-        </s:Body>
-        </s:Envelope>
-        ",
+                <NewRemoteHost></NewRemoteHost>",
             protocol,
             external_port,
             local_addr.ip(),
@@ -146,120 +365,74 @@ impl Gateway {
             lease_duration,
             description
         );
-        let gateway = self.clone();
-        let description = description.to_owned();
         // First, attempt to call the AddAnyPortMapping method.
-        let future = self
-            .perform_request(header, &*body, "AddAnyPortMappingResponse")
-            .and_then(
-                |(text, response)| match response.get_child("NewReservedPort").and_then(|e| e.text.as_ref()).and_then(|t| t.parse::<u16>().ok()) {
-                    Some(port) => Ok(port),
-                    None => Err(RequestError::InvalidResponse(text)),
-                },
-            )
-            .or_else(move |err| {
-                match err {
-                    // The router doesn't know the AddAnyPortMapping method. Try using AddPortMapping
-                    // instead.
-                    RequestError::ErrorCode(401, _) => {
-                        // Try a bunch of random ports.
-                        gateway.retry_add_random_port_mapping(protocol, local_addr, lease_duration, &description)
-                    }
-                    e => {
-                        let err = match e {
-                            RequestError::ErrorCode(605, _) => AddAnyPortError::DescriptionTooLong,
-                            RequestError::ErrorCode(606, _) => AddAnyPortError::ActionNotAuthorized,
-                            RequestError::ErrorCode(728, _) => AddAnyPortError::NoPortsAvailable,
-                            e => AddAnyPortError::RequestError(e),
-                        };
-                        Box::new(future::err(err))
-                    }
-                }
-            });
-        Box::new(future)
+        let result = self.perform_action("AddAnyPortMapping", &args, Idempotent::No).await.and_then(|(text, response)| {
+            match response.get_child("NewReservedPort").and_then(|e| e.text.as_ref()).and_then(|t| t.parse::<u16>().ok()) {
+                Some(port) => Ok(port),
+                None => Err(RequestError::InvalidResponse(text)),
+            }
+        });
+        match result {
+            Ok(port) => Ok(port),
+            // The router doesn't know the AddAnyPortMapping method. Try using AddPortMapping
+            // instead.
+            Err(RequestError::ErrorCode(401, _)) => {
+                // Try a bunch of random ports.
+                self.retry_add_random_port_mapping(protocol, local_addr, lease_duration, description).await
+            }
+            Err(RequestError::ErrorCode(605, _)) => Err(AddAnyPortError::DescriptionTooLong),
+            Err(RequestError::ErrorCode(606, _)) => Err(AddAnyPortError::ActionNotAuthorized),
+            Err(RequestError::ErrorCode(728, _)) => Err(AddAnyPortError::NoPortsAvailable),
+            Err(e) => Err(AddAnyPortError::RequestError(e)),
+        }
     }
 
-    fn retry_add_random_port_mapping(&self, protocol: PortMappingProtocol, local_addr: SocketAddrV4, lease_duration: u32, description: &str) -> Box<Future<Item = u16, Error = AddAnyPortError>> {
-        let description = description.to_owned();
-        let gateway = self.clone();
-        let retry_strategy = FixedInterval::from_millis(0).take(20);
-        let future = RetryIf::spawn(
-            retry_strategy,
-            move || gateway.add_random_port_mapping(protocol, local_addr, lease_duration, &description),
-            |err: &AddAnyPortError| match err {
-                &AddAnyPortError::NoPortsAvailable => true,
-                _ => false,
-            },
-        )
-        .map_err(|err| match err {
-            RetryError::OperationError(e) => e,
-            RetryError::TimerError(io_error) => AddAnyPortError::from(RequestError::from(io_error)),
-        });
-        Box::new(future)
+    async fn retry_add_random_port_mapping(&self, protocol: PortMappingProtocol, local_addr: SocketAddrV4, lease_duration: u32, description: &str) -> Result<u16, AddAnyPortError> {
+        // Up to the number of attempts of the policy, while the ports tried are taken.
+        let mut retries = self.policy.random_port_attempts.saturating_sub(1);
+        loop {
+            match self.add_random_port_mapping(protocol, local_addr, lease_duration, description).await {
+                Err(AddAnyPortError::NoPortsAvailable) if retries > 0 => retries -= 1,
+                result => return result,
+            }
+        }
     }
 
-    fn add_random_port_mapping(&self, protocol: PortMappingProtocol, local_addr: SocketAddrV4, lease_duration: u32, description: &str) -> Box<Future<Item = u16, Error = AddAnyPortError>> {
-        let description = description.to_owned();
-        let gateway = self.clone();
-        let port_range = rand::distributions::Range::new(32_768_u16, 65_535_u16);
-        let mut rng = rand::thread_rng();
-        let external_port = port_range.ind_sample(&mut rng);
-        let future = self
-            .add_port_mapping(protocol, external_port, local_addr, lease_duration, &description)
-            .map(move |_| external_port)
-            .or_else(move |err| match err {
-                RequestError::ErrorCode(724, _) =>
-                // The router requires that internal and external ports are the same.
-                {
-                    gateway.add_same_port_mapping(protocol, local_addr, lease_duration, &description)
-                }
-                e => {
-                    let err = match e {
-                        RequestError::ErrorCode(605, _) => AddAnyPortError::DescriptionTooLong,
-                        RequestError::ErrorCode(606, _) => AddAnyPortError::ActionNotAuthorized,
-                        // That port is in use. Try another.
-                        RequestError::ErrorCode(718, _) => AddAnyPortError::NoPortsAvailable,
-                        RequestError::ErrorCode(725, _) => AddAnyPortError::OnlyPermanentLeasesSupported,
-                        e => AddAnyPortError::RequestError(e),
-                    };
-                    Box::new(future::err(err))
-                }
-            });
-        Box::new(future)
-    }
-
-    fn add_same_port_mapping(&self, protocol: PortMappingProtocol, local_addr: SocketAddrV4, lease_duration: u32, description: &str) -> Box<Future<Item = u16, Error = AddAnyPortError>> {
-        let future = self
-            .add_port_mapping(protocol, local_addr.port(), local_addr, lease_duration, description)
-            .then(move |result| match result {
-                Ok(()) => Ok(local_addr.port()),
-                Err(RequestError::ErrorCode(606, _)) => Err(AddAnyPortError::ActionNotAuthorized),
-                Err(RequestError::ErrorCode(718, _)) => Err(AddAnyPortError::ExternalPortInUse),
-                Err(RequestError::ErrorCode(725, _)) => Err(AddAnyPortError::OnlyPermanentLeasesSupported),
-                Err(e) => Err(AddAnyPortError::RequestError(e)),
-            });
-        Box::new(future)
+    async fn add_random_port_mapping(&self, protocol: PortMappingProtocol, local_addr: SocketAddrV4, lease_duration: u32, description: &str) -> Result<u16, AddAnyPortError> {
+        let external_port = random_port();
+        match self.add_port_mapping(protocol, external_port, local_addr, lease_duration, description).await {
+            Ok(()) => Ok(external_port),
+            // The router requires that internal and external ports are the same.
+            Err(RequestError::ErrorCode(724, _)) => self.add_same_port_mapping(protocol, local_addr, lease_duration, description).await,
+            Err(RequestError::ErrorCode(605, _)) => Err(AddAnyPortError::DescriptionTooLong),
+            Err(RequestError::ErrorCode(606, _)) => Err(AddAnyPortError::ActionNotAuthorized),
+            // That port is in use. Try another.
+            Err(RequestError::ErrorCode(718, _)) => Err(AddAnyPortError::NoPortsAvailable),
+            Err(RequestError::ErrorCode(725, _)) => Err(AddAnyPortError::OnlyPermanentLeasesSupported),
+            Err(e) => Err(AddAnyPortError::RequestError(e)),
+        }
     }
 
-    fn add_port_mapping(&self, protocol: PortMappingProtocol, external_port: u16, local_addr: SocketAddrV4, lease_duration: u32, description: &str) -> Box<Future<Item = (), Error = RequestError>> {
-        let header = "\"urn:schemas-upnp-org:service:WANIPConnection:1#AddPortMapping\"";
-        let body = format!(
-            "<?xml version=\"1.0\"?>
-        <s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">
-        <s:Body>
-            <u:AddPortMapping xmlns:u=\"urn:schemas-upnp-org:service:WANIPConnection:1\">
-                <NewProtocol>{}</NewProtocol>
+    async fn add_same_port_mapping(&self, protocol: PortMappingProtocol, local_addr: SocketAddrV4, lease_duration: u32, description: &str) -> Result<u16, AddAnyPortError> {
+        match self.add_port_mapping(protocol, local_addr.port(), local_addr, lease_duration, description).await {
+            Ok(()) => Ok(local_addr.port()),
+            Err(RequestError::ErrorCode(606, _)) => Err(AddAnyPortError::ActionNotAuthorized),
+            Err(RequestError::ErrorCode(718, _)) => Err(AddAnyPortError::ExternalPortInUse),
+            Err(RequestError::ErrorCode(725, _)) => Err(AddAnyPortError::OnlyPermanentLeasesSupported),
+            Err(e) => Err(AddAnyPortError::RequestError(e)),
+        }
+    }
+
+    async fn add_port_mapping(&self, protocol: PortMappingProtocol, external_port: u16, local_addr: SocketAddrV4, lease_duration: u32, description: &str) -> Result<(), RequestError> {
+        let args = format!(
+            "<NewProtocol>{}</NewProtocol>
                 <NewExternalPort>{}</NewExternalPort>
                 <NewInternalClient>{}</NewInternalClient>
                 <NewInternalPort>{}</NewInternalPort>
                 <NewLeaseDuration>{}</NewLeaseDuration>
                 <NewPortMappingDescription>{}</NewPortMappingDescription>
                 <NewEnabled>1</NewEnabled>
-                <NewRemoteHost></NewRemoteHost>
-            </u:AddPortMapping>
-        </s:Body>
-        </s:Envelope>
-        ",
+                <NewRemoteHost></NewRemoteHost>",
             protocol,
             external_port,
             local_addr.ip(),
@@ -267,56 +440,494 @@ impl Gateway {
             lease_duration,
             description
         );
-        let future = self.perform_request(header, &*body, "AddPortMappingResponse").map(|_| ());
-        Box::new(future)
+        self.perform_action("AddPortMapping", &args, Idempotent::No).await.map(|_| ())
     }
 
     /// Add a port mapping.
     ///
     /// The local_addr is the address where the traffic is sent to.
     /// The lease_duration parameter is in seconds. A value of 0 is infinite.
-    pub fn add_port(&self, protocol: PortMappingProtocol, external_port: u16, local_addr: SocketAddrV4, lease_duration: u32, description: &str) -> Box<Future<Item = (), Error = AddPortError>> {
+    ///
+    /// The mapping stays on the gateway until removed or its lease expires, see
+    /// `add_port_guarded` for a mapping removed when its handle is dropped.
+    pub async fn add_port(&self, protocol: PortMappingProtocol, external_port: u16, local_addr: SocketAddrV4, lease_duration: u32, description: &str) -> Result<(), AddPortError> {
         if external_port == 0 {
-            return Box::new(future::err(AddPortError::ExternalPortZeroInvalid));
+            return Err(AddPortError::ExternalPortZeroInvalid);
         }
         if local_addr.port() == 0 {
-            return Box::new(future::err(AddPortError::InternalPortZeroInvalid));
+            return Err(AddPortError::InternalPortZeroInvalid);
         }
-        let future = self.add_port_mapping(protocol, external_port, local_addr, lease_duration, description).map_err(|err| match err {
+        self.add_port_mapping(protocol, external_port, local_addr, lease_duration, description).await.map_err(|err| match err {
             RequestError::ErrorCode(605, _) => AddPortError::DescriptionTooLong,
             RequestError::ErrorCode(606, _) => AddPortError::ActionNotAuthorized,
             RequestError::ErrorCode(718, _) => AddPortError::PortInUse,
             RequestError::ErrorCode(724, _) => AddPortError::SamePortValuesRequired,
             RequestError::ErrorCode(725, _) => AddPortError::OnlyPermanentLeasesSupported,
             e => AddPortError::RequestError(e),
-        });
-        Box::new(future)
+        })
     }
 
     /// Remove a port mapping.
-    pub fn remove_port(&self, protocol: PortMappingProtocol, external_port: u16) -> Box<Future<Item = (), Error = RemovePortError>> {
-        let header = "\"urn:schemas-upnp-org:service:WANIPConnection:1#DeletePortMapping\"";
-        let body = format!(
-            "<?xml version=\"1.0\"?>
-        <s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">
-        <s:Body>
-            <u:DeletePortMapping xmlns:u=\"urn:schemas-upnp-org:service:WANIPConnection:1\">
-                <NewProtocol>{}</NewProtocol>
+    pub async fn remove_port(&self, protocol: PortMappingProtocol, external_port: u16) -> Result<(), RemovePortError> {
+        self.delete_port_mapping(protocol, external_port, "").await
+    }
+
+    async fn delete_port_mapping(&self, protocol: PortMappingProtocol, external_port: u16, remote_host: &str) -> Result<(), RemovePortError> {
+        let args = format!(
+            "<NewProtocol>{}</NewProtocol>
                 <NewExternalPort>{}</NewExternalPort>
-                <NewRemoteHost></NewRemoteHost>
-            </u:DeletePortMapping>
-        </s:Body>
-        </s:Envelope>
-        ",
-            protocol, external_port
+                <NewRemoteHost>{}</NewRemoteHost>",
+            protocol, external_port, remote_host
         );
 
-        let future = self.perform_request(header, &*body, "DeletePortMappingResponse").map(|_| ()).map_err(|err| match err {
+        self.perform_action("DeletePortMapping", &args, Idempotent::No).await.map(|_| ()).map_err(|err| match err {
             RequestError::ErrorCode(606, _) => RemovePortError::ActionNotAuthorized,
             RequestError::ErrorCode(714, _) => RemovePortError::NoSuchPortMapping,
             e => RemovePortError::RequestError(e),
+        })
+    }
+
+    /// Get the port mapping at the given index of the port mapping table of the gateway.
+    pub async fn get_generic_port_mapping_entry(&self, index: u32) -> Result<PortMappingEntry, GetGenericPortMappingEntryError> {
+        let args = format!("<NewPortMappingIndex>{}</NewPortMappingIndex>", index);
+        match self.perform_action("GetGenericPortMappingEntry", &args, Idempotent::Yes).await {
+            Ok((text, response)) => parse_port_mapping_entry(text, &response).map_err(GetGenericPortMappingEntryError::RequestError),
+            Err(RequestError::ErrorCode(606, _)) => Err(GetGenericPortMappingEntryError::ActionNotAuthorized),
+            // Some gateways answer NoSuchEntryInArray past the end of the table.
+            Err(RequestError::ErrorCode(713, _)) | Err(RequestError::ErrorCode(714, _)) => Err(GetGenericPortMappingEntryError::SpecifiedArrayIndexInvalid),
+            Err(e) => Err(GetGenericPortMappingEntryError::RequestError(e)),
+        }
+    }
+
+    /// Get the port mapping of the given external port.
+    ///
+    /// The remote_host is the remote host the mapping is restricted to. Use an empty string for
+    /// a mapping allowing any remote host.
+    pub async fn get_port_mapping(&self, protocol: PortMappingProtocol, external_port: u16, remote_host: &str) -> Result<PortMappingEntry, GetPortMappingError> {
+        let args = format!(
+            "<NewRemoteHost>{}</NewRemoteHost>
+                <NewExternalPort>{}</NewExternalPort>
+                <NewProtocol>{}</NewProtocol>",
+            remote_host, external_port, protocol
+        );
+        match self.perform_action("GetSpecificPortMappingEntry", &args, Idempotent::Yes).await {
+            Ok((text, response)) => {
+                parse_specific_port_mapping_entry(text, &response, remote_host.to_owned(), external_port, protocol).map_err(GetPortMappingError::RequestError)
+            }
+            Err(RequestError::ErrorCode(606, _)) => Err(GetPortMappingError::ActionNotAuthorized),
+            Err(RequestError::ErrorCode(714, _)) => Err(GetPortMappingError::NoSuchEntryInArray),
+            Err(e) => Err(GetPortMappingError::RequestError(e)),
+        }
+    }
+
+    /// Get all the port mappings of the gateway.
+    ///
+    /// The port mapping table is read one entry at a time, the stream ends after the last entry
+    /// or after the first error.
+    pub fn port_mappings(&self) -> BoxStream<'static, Result<PortMappingEntry, GetGenericPortMappingEntryError>> {
+        let gateway = self.clone();
+        let stream = stream::unfold(Some(0), move |index| {
+            let gateway = gateway.clone();
+            async move {
+                let index = index?;
+                match gateway.get_generic_port_mapping_entry(index).await {
+                    Ok(entry) => Some((Ok(entry), Some(index + 1))),
+                    Err(GetGenericPortMappingEntryError::SpecifiedArrayIndexInvalid) => None,
+                    Err(e) => Some((Err(e), None)),
+                }
+            }
         });
-        Box::new(future)
+        Box::pin(stream)
+    }
+
+    /// Get the port mappings of the gateway with an external port in the given range.
+    ///
+    /// When manage is false, only the mappings of this client are returned. At most max_entries
+    /// mappings are returned, a value of 0 returns all of them.
+    ///
+    /// This uses GetListOfPortMappings, which only exists in version 2 of the service. Other
+    /// gateways are searched one entry at a time with `port_mappings`, which returns the mappings
+    /// of all clients regardless of manage.
+    pub async fn list_port_mappings(
+        &self,
+        start_port: u16,
+        end_port: u16,
+        protocol: PortMappingProtocol,
+        manage: bool,
+        max_entries: u16,
+    ) -> Result<Vec<PortMappingEntry>, ListPortMappingsError> {
+        if self.version() < 2 {
+            return self.list_generic_port_mappings(start_port, end_port, protocol, max_entries).await;
+        }
+
+        let args = format!(
+            "<NewStartPort>{}</NewStartPort>
+                <NewEndPort>{}</NewEndPort>
+                <NewProtocol>{}</NewProtocol>
+                <NewManage>{}</NewManage>
+                <NewNumberOfPorts>{}</NewNumberOfPorts>",
+            start_port, end_port, protocol, manage as u8, max_entries
+        );
+        match self.perform_action("GetListOfPortMappings", &args, Idempotent::Yes).await {
+            Ok((text, response)) => parse_port_listing(text, &response).map_err(ListPortMappingsError::RequestError),
+            // The gateway doesn't know the GetListOfPortMappings method. Read the entries one by one instead.
+            Err(RequestError::ErrorCode(401, _)) => self.list_generic_port_mappings(start_port, end_port, protocol, max_entries).await,
+            Err(RequestError::ErrorCode(606, _)) => Err(ListPortMappingsError::ActionNotAuthorized),
+            // There is no mapping in the range.
+            Err(RequestError::ErrorCode(730, _)) => Ok(Vec::new()),
+            Err(RequestError::ErrorCode(733, _)) => Err(ListPortMappingsError::InconsistentParameters),
+            Err(e) => Err(ListPortMappingsError::RequestError(e)),
+        }
+    }
+
+    /// Remove the port mappings with an external port in the given range.
+    ///
+    /// When manage is false, only the mappings of this client are removed.
+    ///
+    /// The mappings of the range are listed first. Gateways offering version 2 of the service
+    /// remove them with a single call to DeletePortMappingRange. If the gateway does not implement
+    /// that action, the mappings are removed one by one.
+    ///
+    /// # Returns
+    ///
+    /// Which mappings were removed and which were not on success. An error if the mappings of the
+    /// range could not be listed, or if DeletePortMappingRange failed for another reason than the
+    /// client not being authorized.
+    ///
+    /// The report is best-effort when DeletePortMappingRange is used: it is made from the listing
+    /// done beforehand, so it misses the mappings added to or removed from the range in between,
+    /// and the ones the gateway left out of the listing. When the client is not authorized, every
+    /// listed mapping is reported as failed with `RemovePortError::ActionNotAuthorized`.
+    pub async fn remove_port_range(&self, protocol: PortMappingProtocol, start_port: u16, end_port: u16, manage: bool) -> Result<RemovePortRangeReport, ListPortMappingsError> {
+        let entries = self.list_port_mappings(start_port, end_port, protocol, manage, 0).await?;
+        if entries.is_empty() {
+            return Ok(RemovePortRangeReport::default());
+        }
+        if self.version() < 2 {
+            return Ok(self.remove_port_mappings(entries).await);
+        }
+
+        let args = format!(
+            "<NewStartPort>{}</NewStartPort>
+                <NewEndPort>{}</NewEndPort>
+                <NewProtocol>{}</NewProtocol>
+                <NewManage>{}</NewManage>",
+            start_port, end_port, protocol, manage as u8
+        );
+        match self.perform_action("DeletePortMappingRange", &args, Idempotent::No).await {
+            Ok(_) => Ok(RemovePortRangeReport {
+                removed: entries.iter().map(|entry| entry.external_port).collect(),
+                failed: Vec::new(),
+            }),
+            // The gateway doesn't know the DeletePortMappingRange method. Remove the mappings one by one instead.
+            Err(RequestError::ErrorCode(401, _)) | Err(RequestError::ErrorCode(602, _)) => Ok(self.remove_port_mappings(entries).await),
+            // The other mappings would be refused too, don't ask for each of them.
+            Err(RequestError::ErrorCode(606, _)) => Ok(RemovePortRangeReport {
+                removed: Vec::new(),
+                failed: entries.iter().map(|entry| (entry.external_port, RemovePortError::ActionNotAuthorized)).collect(),
+            }),
+            // The mappings were removed since they were listed.
+            Err(RequestError::ErrorCode(730, _)) => Ok(RemovePortRangeReport::default()),
+            Err(RequestError::ErrorCode(733, _)) => Err(ListPortMappingsError::InconsistentParameters),
+            Err(e) => Err(ListPortMappingsError::RequestError(e)),
+        }
+    }
+
+    async fn remove_port_mappings(&self, entries: Vec<PortMappingEntry>) -> RemovePortRangeReport {
+        let mut report = RemovePortRangeReport::default();
+        for entry in entries {
+            match self.delete_port_mapping(entry.protocol, entry.external_port, &entry.remote_host).await {
+                Ok(()) => report.removed.push(entry.external_port),
+                Err(e) => report.failed.push((entry.external_port, e)),
+            }
+        }
+        report
+    }
+
+    async fn list_generic_port_mappings(&self, start_port: u16, end_port: u16, protocol: PortMappingProtocol, max_entries: u16) -> Result<Vec<PortMappingEntry>, ListPortMappingsError> {
+        let max_entries = if max_entries == 0 { usize::MAX } else { max_entries as usize };
+        let mut entries = Vec::new();
+        let mut index = 0;
+        while entries.len() < max_entries {
+            let entry = match self.get_generic_port_mapping_entry(index).await {
+                Ok(entry) => entry,
+                // The end of the port mapping table.
+                Err(GetGenericPortMappingEntryError::SpecifiedArrayIndexInvalid) => break,
+                Err(GetGenericPortMappingEntryError::ActionNotAuthorized) => return Err(ListPortMappingsError::ActionNotAuthorized),
+                Err(GetGenericPortMappingEntryError::RequestError(e)) => return Err(ListPortMappingsError::RequestError(e)),
+            };
+            if entry.protocol == protocol && entry.external_port >= start_port && entry.external_port <= end_port {
+                entries.push(entry);
+            }
+            index += 1;
+        }
+        Ok(entries)
+    }
+}
+
+// Whether an action can be sent again when it may have reached the gateway already. Actions
+// reading the state of the gateway, or setting a value that is the same when set twice, are.
+// Sending the others again could add or delete a mapping twice.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Idempotent {
+    Yes,
+    No,
+}
+
+// A random port in the dynamic range, to try to map.
+fn random_port() -> u16 {
+    let port_range = rand::distributions::Range::new(32_768_u16, 65_535_u16);
+    let mut rng = rand::thread_rng();
+    port_range.ind_sample(&mut rng)
+}
+
+/// A port mapping of the port mapping table of a gateway.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PortMappingEntry {
+    /// The remote host the mapping is restricted to. Empty if any remote host is allowed.
+    pub remote_host: String,
+    /// The external port of the mapping.
+    pub external_port: u16,
+    /// The protocol of the mapping.
+    pub protocol: PortMappingProtocol,
+    /// The internal client the traffic is sent to.
+    pub internal_client: String,
+    /// The port on the internal client the traffic is sent to.
+    pub internal_port: u16,
+    /// Whether the mapping is enabled.
+    pub enabled: bool,
+    /// The description of the mapping.
+    pub description: String,
+    /// The remaining lease duration in seconds. A value of 0 is infinite.
+    pub lease_duration: u32,
+}
+
+// Get the text of the given child element, empty if the element has no text.
+pub(crate) fn child_text<'a>(response: &'a xmltree::Element, name: &str) -> Option<&'a str> {
+    response.get_child(name).map(|e| e.text.as_ref().map(|t| t.trim()).unwrap_or(""))
+}
+
+/// The status of the connection of a gateway, as returned by `Gateway::status_info`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StatusInfo {
+    /// The status of the connection.
+    pub connection_status: ConnectionStatus,
+    /// The reason of the last disconnection.
+    pub last_connection_error: LastConnectionError,
+    /// How long the connection has been up.
+    pub uptime: Duration,
+}
+
+/// The status of the connection of a gateway.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConnectionStatus {
+    /// The connection is not configured.
+    Unconfigured,
+    /// The connection is being established.
+    Connecting,
+    /// The gateway is authenticating to the ISP.
+    Authenticating,
+    /// The connection is established.
+    Connected,
+    /// The connection will be closed after the disconnection delay.
+    PendingDisconnect,
+    /// The connection is being closed.
+    Disconnecting,
+    /// The connection is closed.
+    Disconnected,
+    /// A status not defined by the specification.
+    Other(String),
+}
+
+impl ConnectionStatus {
+    pub(crate) fn parse(text: &str) -> ConnectionStatus {
+        match text {
+            "Unconfigured" => ConnectionStatus::Unconfigured,
+            "Connecting" => ConnectionStatus::Connecting,
+            "Authenticating" => ConnectionStatus::Authenticating,
+            "Connected" => ConnectionStatus::Connected,
+            "PendingDisconnect" => ConnectionStatus::PendingDisconnect,
+            "Disconnecting" => ConnectionStatus::Disconnecting,
+            "Disconnected" => ConnectionStatus::Disconnected,
+            other => ConnectionStatus::Other(other.to_owned()),
+        }
+    }
+}
+
+/// The reason of the last disconnection of a gateway.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LastConnectionError {
+    /// No error.
+    None,
+    /// The connection attempt was aborted.
+    CommandAborted,
+    /// The connection is not enabled for internet access.
+    NotEnabledForInternet,
+    /// The user closed the connection.
+    UserDisconnect,
+    /// The ISP closed the connection.
+    IspDisconnect,
+    /// The connection was closed because it was idle.
+    IdleDisconnect,
+    /// The connection was forcibly closed.
+    ForcedDisconnect,
+    /// There is no carrier on the WAN link.
+    NoCarrier,
+    /// The IP configuration of the connection failed.
+    IpConfiguration,
+    /// An unknown error.
+    Unknown,
+    /// An error not defined by the specification, such as the PPP specific ones.
+    Other(String),
+}
+
+impl LastConnectionError {
+    fn parse(text: &str) -> LastConnectionError {
+        match text {
+            "ERROR_NONE" => LastConnectionError::None,
+            "ERROR_COMMAND_ABORTED" => LastConnectionError::CommandAborted,
+            "ERROR_NOT_ENABLED_FOR_INTERNET" => LastConnectionError::NotEnabledForInternet,
+            "ERROR_USER_DISCONNECT" => LastConnectionError::UserDisconnect,
+            "ERROR_ISP_DISCONNECT" => LastConnectionError::IspDisconnect,
+            "ERROR_IDLE_DISCONNECT" => LastConnectionError::IdleDisconnect,
+            "ERROR_FORCED_DISCONNECT" => LastConnectionError::ForcedDisconnect,
+            "ERROR_NO_CARRIER" => LastConnectionError::NoCarrier,
+            "ERROR_IP_CONFIGURATION" => LastConnectionError::IpConfiguration,
+            "ERROR_UNKNOWN" => LastConnectionError::Unknown,
+            other => LastConnectionError::Other(other.to_owned()),
+        }
+    }
+}
+
+/// The NAT and RSIP status of a gateway, as returned by `Gateway::nat_rsip_status`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NatRsipStatus {
+    /// Whether the gateway supports Realm-Specific IP.
+    pub rsip_available: bool,
+    /// Whether the gateway translates addresses.
+    pub nat_enabled: bool,
+}
+
+/// The connection type of a gateway, as returned by `Gateway::connection_type_info`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConnectionTypeInfo {
+    /// The current type of the connection.
+    pub connection_type: ConnectionType,
+    /// The types the connection can be set to.
+    pub possible_connection_types: Vec<ConnectionType>,
+}
+
+/// The type of the connection of a gateway.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConnectionType {
+    /// The connection type is not set.
+    Unconfigured,
+    /// The gateway routes traffic between the LAN and the WAN.
+    IpRouted,
+    /// The gateway bridges the LAN and the WAN, without NAT.
+    IpBridged,
+    /// A type not defined by the WANIPConnection specification, such as the PPP specific ones.
+    Other(String),
+}
+
+impl ConnectionType {
+    fn parse(text: &str) -> ConnectionType {
+        match text {
+            "Unconfigured" => ConnectionType::Unconfigured,
+            "IP_Routed" => ConnectionType::IpRouted,
+            "IP_Bridged" => ConnectionType::IpBridged,
+            other => ConnectionType::Other(other.to_owned()),
+        }
+    }
+}
+
+/// The outcome of `Gateway::remove_port_range`.
+#[derive(Debug, Default)]
+pub struct RemovePortRangeReport {
+    /// The external ports whose mapping was removed.
+    pub removed: Vec<u16>,
+    /// The external ports whose mapping could not be removed, with the reason.
+    pub failed: Vec<(u16, RemovePortError)>,
+}
+
+fn parse_port_mapping_entry(text: String, response: &xmltree::Element) -> Result<PortMappingEntry, RequestError> {
+    let entry = (|| {
+        Some(PortMappingEntry {
+            remote_host: child_text(response, "NewRemoteHost").unwrap_or("").to_owned(),
+            external_port: child_text(response, "NewExternalPort")?.parse().ok()?,
+            protocol: parse_protocol(child_text(response, "NewProtocol")?)?,
+            internal_client: child_text(response, "NewInternalClient")?.to_owned(),
+            internal_port: child_text(response, "NewInternalPort")?.parse().ok()?,
+            enabled: parse_bool(child_text(response, "NewEnabled")?)?,
+            description: child_text(response, "NewPortMappingDescription").unwrap_or("").to_owned(),
+            lease_duration: child_text(response, "NewLeaseDuration").unwrap_or("0").parse().ok()?,
+        })
+    })();
+    entry.ok_or(RequestError::InvalidResponse(text))
+}
+
+// The listing returned by GetListOfPortMappings is an escaped XML document of its own.
+fn parse_port_listing(text: String, response: &xmltree::Element) -> Result<Vec<PortMappingEntry>, RequestError> {
+    let listing = match child_text(response, "NewPortListing").map(|listing| xmltree::Element::parse(listing.as_bytes())) {
+        Some(Ok(listing)) => listing,
+        _ => return Err(RequestError::InvalidResponse(text)),
+    };
+    let entries = listing
+        .children
+        .iter()
+        .filter(|child| child.name == "PortMappingEntry")
+        .map(|entry| {
+            Some(PortMappingEntry {
+                remote_host: child_text(entry, "NewRemoteHost").unwrap_or("").to_owned(),
+                external_port: child_text(entry, "NewExternalPort")?.parse().ok()?,
+                protocol: parse_protocol(child_text(entry, "NewProtocol")?)?,
+                internal_client: child_text(entry, "NewInternalClient")?.to_owned(),
+                internal_port: child_text(entry, "NewInternalPort")?.parse().ok()?,
+                enabled: parse_bool(child_text(entry, "NewEnabled")?)?,
+                description: child_text(entry, "NewDescription").unwrap_or("").to_owned(),
+                lease_duration: child_text(entry, "NewLeaseTime").unwrap_or("0").parse().ok()?,
+            })
+        })
+        .collect::<Option<Vec<_>>>();
+    entries.ok_or(RequestError::InvalidResponse(text))
+}
+
+// The response to GetSpecificPortMappingEntry does not repeat the arguments of the request.
+fn parse_specific_port_mapping_entry(
+    text: String,
+    response: &xmltree::Element,
+    remote_host: String,
+    external_port: u16,
+    protocol: PortMappingProtocol,
+) -> Result<PortMappingEntry, RequestError> {
+    let entry = (|| {
+        Some(PortMappingEntry {
+            remote_host,
+            external_port,
+            protocol,
+            internal_client: child_text(response, "NewInternalClient")?.to_owned(),
+            internal_port: child_text(response, "NewInternalPort")?.parse().ok()?,
+            enabled: parse_bool(child_text(response, "NewEnabled")?)?,
+            description: child_text(response, "NewPortMappingDescription").unwrap_or("").to_owned(),
+            lease_duration: child_text(response, "NewLeaseDuration").unwrap_or("0").parse().ok()?,
+        })
+    })();
+    entry.ok_or(RequestError::InvalidResponse(text))
+}
+
+fn parse_protocol(text: &str) -> Option<PortMappingProtocol> {
+    match text {
+        "TCP" | "tcp" => Some(PortMappingProtocol::TCP),
+        "UDP" | "udp" => Some(PortMappingProtocol::UDP),
+        _ => None,
+    }
+}
+
+pub(crate) fn parse_bool(text: &str) -> Option<bool> {
+    match text {
+        "1" | "true" | "yes" => Some(true),
+        "0" | "false" | "no" => Some(false),
+        _ => None,
     }
 }
 
@@ -328,7 +939,7 @@ impl fmt::Display for Gateway {
 
 impl PartialEq for Gateway {
     fn eq(&self, other: &Gateway) -> bool {
-        self.addr == other.addr && self.control_url == other.control_url
+        self.addr == other.addr && self.control_url == other.control_url && self.service_type == other.service_type
     }
 }
 
@@ -338,6 +949,7 @@ impl Hash for Gateway {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.addr.hash(state);
         self.control_url.hash(state);
+        self.service_type.hash(state);
     }
 }
 
@@ -368,3 +980,442 @@ fn parse_response(text: String, ok: &str) -> Result<(String, xmltree::Element),
         _ => Err(RequestError::InvalidResponse(text)),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::test::{envelope, fault, FnTransport};
+    use futures::future;
+    use std::sync::atomic::Ordering::SeqCst;
+    use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize};
+
+    #[test]
+    fn test_parse_port_mapping_entry() {
+        let text = r#"<?xml version="1.0"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">
+<s:Body>
+<u:GetGenericPortMappingEntryResponse xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+<NewRemoteHost></NewRemoteHost>
+<NewExternalPort>8080</NewExternalPort>
+<NewProtocol>TCP</NewProtocol>
+<NewInternalPort>80</NewInternalPort>
+<NewInternalClient>192.168.1.10</NewInternalClient>
+<NewEnabled>1</NewEnabled>
+<NewPortMappingDescription>web server</NewPortMappingDescription>
+<NewLeaseDuration>3600</NewLeaseDuration>
+</u:GetGenericPortMappingEntryResponse>
+</s:Body>
+</s:Envelope>"#;
+        let (text, response) = parse_response(text.to_owned(), "GetGenericPortMappingEntryResponse").unwrap();
+        let entry = parse_port_mapping_entry(text, &response).unwrap();
+        assert_eq!(
+            entry,
+            PortMappingEntry {
+                remote_host: "".to_owned(),
+                external_port: 8080,
+                protocol: PortMappingProtocol::TCP,
+                internal_client: "192.168.1.10".to_owned(),
+                internal_port: 80,
+                enabled: true,
+                description: "web server".to_owned(),
+                lease_duration: 3600,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_port_listing() {
+        let text = r#"<?xml version="1.0"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">
+<s:Body>
+<u:GetListOfPortMappingsResponse xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:2">
+<NewPortListing>&lt;?xml version="1.0" encoding="UTF-8"?&gt;
+&lt;p:PortMappingList xmlns:p="urn:schemas-upnp-org:gw:WANIPConnection"&gt;
+&lt;p:PortMappingEntry&gt;
+&lt;p:NewRemoteHost&gt;&lt;/p:NewRemoteHost&gt;
+&lt;p:NewExternalPort&gt;5000&lt;/p:NewExternalPort&gt;
+&lt;p:NewProtocol&gt;UDP&lt;/p:NewProtocol&gt;
+&lt;p:NewInternalPort&gt;5001&lt;/p:NewInternalPort&gt;
+&lt;p:NewInternalClient&gt;192.168.1.20&lt;/p:NewInternalClient&gt;
+&lt;p:NewEnabled&gt;1&lt;/p:NewEnabled&gt;
+&lt;p:NewDescription&gt;game&lt;/p:NewDescription&gt;
+&lt;p:NewLeaseTime&gt;0&lt;/p:NewLeaseTime&gt;
+&lt;/p:PortMappingEntry&gt;
+&lt;/p:PortMappingList&gt;</NewPortListing>
+</u:GetListOfPortMappingsResponse>
+</s:Body>
+</s:Envelope>"#;
+        let (text, response) = parse_response(text.to_owned(), "GetListOfPortMappingsResponse").unwrap();
+        let entries = parse_port_listing(text, &response).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].external_port, 5000);
+        assert_eq!(entries[0].protocol, PortMappingProtocol::UDP);
+        assert_eq!(entries[0].internal_client, "192.168.1.20");
+        assert_eq!(entries[0].internal_port, 5001);
+        assert_eq!(entries[0].description, "game");
+    }
+
+    #[test]
+    fn test_parse_response_error_code() {
+        let text = r#"<?xml version="1.0"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">
+<s:Body>
+<s:Fault>
+<faultcode>s:Client</faultcode>
+<faultstring>UPnPError</faultstring>
+<detail>
+<UPnPError xmlns="urn:schemas-upnp-org:control-1-0">
+<errorCode>713</errorCode>
+<errorDescription>SpecifiedArrayIndexInvalid</errorDescription>
+</UPnPError>
+</detail>
+</s:Fault>
+</s:Body>
+</s:Envelope>"#;
+        match parse_response(text.to_owned(), "GetGenericPortMappingEntryResponse") {
+            Err(RequestError::ErrorCode(713, _)) => (),
+            other => panic!("unexpected result {:?}", other.map(|(text, _)| text)),
+        }
+    }
+
+    // A gateway answering every action with the given output arguments.
+    fn gateway_answering(args: &'static str) -> Gateway {
+        let transport = FnTransport::new(move |action, _| Ok(envelope(action, args)));
+        Gateway::new("192.168.1.1:5000".parse().unwrap(), "/ctl/IPConn".to_owned(), "urn:schemas-upnp-org:service:WANIPConnection:1".to_owned())
+            .with_transport(transport)
+    }
+
+    #[test]
+    fn test_parse_status_info() {
+        let status_info = |connection_status, last_connection_error, uptime| StatusInfo {
+            connection_status,
+            last_connection_error,
+            uptime: Duration::from_secs(uptime),
+        };
+        let cases = [
+            (
+                "<NewConnectionStatus>Connected</NewConnectionStatus><NewLastConnectionError>ERROR_NONE</NewLastConnectionError><NewUptime>3600</NewUptime>",
+                Some(status_info(ConnectionStatus::Connected, LastConnectionError::None, 3600)),
+            ),
+            (
+                "<NewConnectionStatus>Disconnected</NewConnectionStatus><NewLastConnectionError>ERROR_ISP_DISCONNECT</NewLastConnectionError><NewUptime>0</NewUptime>",
+                Some(status_info(ConnectionStatus::Disconnected, LastConnectionError::IspDisconnect, 0)),
+            ),
+            // The PPP specific values are kept as they are.
+            (
+                "<NewConnectionStatus>Connected</NewConnectionStatus><NewLastConnectionError>ERROR_AUTHENTICATION_FAILURE</NewLastConnectionError><NewUptime>12</NewUptime>",
+                Some(status_info(ConnectionStatus::Connected, LastConnectionError::Other("ERROR_AUTHENTICATION_FAILURE".to_owned()), 12)),
+            ),
+            // Some gateways leave out the last error when there is none.
+            (
+                "<NewConnectionStatus>Connecting</NewConnectionStatus><NewUptime>5</NewUptime>",
+                Some(status_info(ConnectionStatus::Connecting, LastConnectionError::None, 5)),
+            ),
+            ("<NewConnectionStatus>Connected</NewConnectionStatus><NewLastConnectionError>ERROR_NONE</NewLastConnectionError>", None),
+            ("<NewConnectionStatus>Connected</NewConnectionStatus><NewUptime>-1</NewUptime>", None),
+            ("<NewLastConnectionError>ERROR_NONE</NewLastConnectionError><NewUptime>3600</NewUptime>", None),
+        ];
+        for (args, expected) in cases {
+            match (crate::runtime::block_on(gateway_answering(args).status_info()), expected) {
+                (Ok(status_info), Some(expected)) => assert_eq!(status_info, expected),
+                (Err(GetStatusInfoError::RequestError(RequestError::InvalidResponse(_))), None) => (),
+                (other, _) => panic!("unexpected result for {}: {:?}", args, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_nat_rsip_status() {
+        let cases = [
+            (
+                "<NewRSIPAvailable>0</NewRSIPAvailable><NewNATEnabled>1</NewNATEnabled>",
+                Some(NatRsipStatus {
+                    rsip_available: false,
+                    nat_enabled: true,
+                }),
+            ),
+            (
+                "<NewRSIPAvailable>true</NewRSIPAvailable><NewNATEnabled>false</NewNATEnabled>",
+                Some(NatRsipStatus {
+                    rsip_available: true,
+                    nat_enabled: false,
+                }),
+            ),
+            ("<NewRSIPAvailable>0</NewRSIPAvailable>", None),
+            ("<NewRSIPAvailable>0</NewRSIPAvailable><NewNATEnabled>maybe</NewNATEnabled>", None),
+        ];
+        for (args, expected) in cases {
+            match (crate::runtime::block_on(gateway_answering(args).nat_rsip_status()), expected) {
+                (Ok(status), Some(expected)) => assert_eq!(status, expected),
+                (Err(GetConnectionInfoError::RequestError(RequestError::InvalidResponse(_))), None) => (),
+                (other, _) => panic!("unexpected result for {}: {:?}", args, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_connection_type_info() {
+        let cases = [
+            (
+                "<NewConnectionType>IP_Routed</NewConnectionType><NewPossibleConnectionTypes>Unconfigured, IP_Routed,IP_Bridged</NewPossibleConnectionTypes>",
+                Some(ConnectionTypeInfo {
+                    connection_type: ConnectionType::IpRouted,
+                    possible_connection_types: vec![ConnectionType::Unconfigured, ConnectionType::IpRouted, ConnectionType::IpBridged],
+                }),
+            ),
+            (
+                "<NewConnectionType>PPPoE_Bridged</NewConnectionType><NewPossibleConnectionTypes>PPPoE_Bridged,</NewPossibleConnectionTypes>",
+                Some(ConnectionTypeInfo {
+                    connection_type: ConnectionType::Other("PPPoE_Bridged".to_owned()),
+                    possible_connection_types: vec![ConnectionType::Other("PPPoE_Bridged".to_owned())],
+                }),
+            ),
+            (
+                "<NewConnectionType>Unconfigured</NewConnectionType><NewPossibleConnectionTypes></NewPossibleConnectionTypes>",
+                Some(ConnectionTypeInfo {
+                    connection_type: ConnectionType::Unconfigured,
+                    possible_connection_types: Vec::new(),
+                }),
+            ),
+            ("<NewPossibleConnectionTypes>IP_Routed</NewPossibleConnectionTypes>", None),
+        ];
+        for (args, expected) in cases {
+            match (crate::runtime::block_on(gateway_answering(args).connection_type_info()), expected) {
+                (Ok(info), Some(expected)) => assert_eq!(info, expected),
+                (Err(GetConnectionInfoError::RequestError(RequestError::InvalidResponse(_))), None) => (),
+                (other, _) => panic!("unexpected result for {}: {:?}", args, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_port_mapping_number_of_entries() {
+        let cases = [
+            ("<NewPortMappingNumberOfEntries>12</NewPortMappingNumberOfEntries>", Some(12)),
+            ("<NewPortMappingNumberOfEntries>0</NewPortMappingNumberOfEntries>", Some(0)),
+            ("<NewPortMappingNumberOfEntries>many</NewPortMappingNumberOfEntries>", None),
+            ("", None),
+        ];
+        for (args, expected) in cases {
+            match (crate::runtime::block_on(gateway_answering(args).port_mapping_number_of_entries()), expected) {
+                (Ok(entries), Some(expected)) => assert_eq!(entries, expected),
+                (Err(GetConnectionInfoError::RequestError(RequestError::InvalidResponse(_))), None) => (),
+                (other, _) => panic!("unexpected result for {}: {:?}", args, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_futures_are_send() {
+        fn assert_send<T: Send>(_: T) {}
+        let gateway = Gateway::new("192.168.1.1:5000".parse().unwrap(), "/ctl/IPConn".to_owned(), "urn:schemas-upnp-org:service:WANIPConnection:1".to_owned());
+        let local_addr = "192.168.1.20:8080".parse().unwrap();
+        assert_send(gateway.get_external_ip());
+        assert_send(gateway.add_any_port(PortMappingProtocol::TCP, local_addr, 60, "test"));
+        assert_send(gateway.remove_port_range(PortMappingProtocol::TCP, 5000, 5010, false));
+        assert_send(gateway.port_mappings());
+        assert_send(gateway.check_pinhole_working(42));
+        assert_send(gateway.common_link_properties());
+        assert_send(crate::search_gateway());
+        assert_send(crate::search_gateways());
+    }
+
+    fn external_ip_response() -> String {
+        envelope("GetExternalIPAddress", "<NewExternalIPAddress>203.0.113.7</NewExternalIPAddress>")
+    }
+
+    #[test]
+    fn test_requests_go_through_transport() {
+        let transport = FnTransport::new(|_, request| {
+            assert_eq!(request.uri(), "http://192.168.1.1:5000/ctl/IPConn");
+            assert_eq!(request.headers()["SOAPAction"], "\"urn:schemas-upnp-org:service:WANIPConnection:1#GetExternalIPAddress\"");
+            Ok(external_ip_response())
+        });
+        let gateway = Gateway::new("192.168.1.1:5000".parse().unwrap(), "/ctl/IPConn".to_owned(), "urn:schemas-upnp-org:service:WANIPConnection:1".to_owned())
+            .with_transport(transport.clone());
+        let ip = crate::runtime::block_on(gateway.get_external_ip()).unwrap();
+        assert_eq!(ip, Ipv4Addr::new(203, 0, 113, 7));
+        assert_eq!(transport.actions(), ["GetExternalIPAddress"]);
+    }
+
+    #[test]
+    fn test_transient_errors_are_retried() {
+        let policy = RequestPolicy {
+            retries: 1,
+            initial_backoff: Duration::from_millis(1),
+            ..RequestPolicy::default()
+        };
+        // The first requests fail with a reset connection, as many as there are failures left.
+        let failures = Arc::new(AtomicU32::new(1));
+        let failures_left = failures.clone();
+        let transport = FnTransport::new(move |_, _| match failures_left.fetch_update(SeqCst, SeqCst, |n| n.checked_sub(1)) {
+            Ok(_) => Err(crate::TransportError::IoError(std::io::ErrorKind::ConnectionReset.into())),
+            Err(_) => Ok(external_ip_response()),
+        });
+        let gateway = Gateway::new("192.168.1.1:5000".parse().unwrap(), "/ctl/IPConn".to_owned(), "urn:schemas-upnp-org:service:WANIPConnection:1".to_owned())
+            .with_transport(transport.clone())
+            .with_request_policy(policy);
+        let ip = crate::runtime::block_on(gateway.get_external_ip()).unwrap();
+        assert_eq!(ip, Ipv4Addr::new(203, 0, 113, 7));
+        assert_eq!(transport.actions().len(), 2);
+
+        // Once the retries are used up, the error is returned.
+        failures.store(2, SeqCst);
+        match crate::runtime::block_on(gateway.get_external_ip()) {
+            Err(GetExternalIpError::RequestError(RequestError::IoError(e))) => assert_eq!(e.kind(), std::io::ErrorKind::ConnectionReset),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(transport.actions().len(), 4);
+    }
+
+    #[test]
+    fn test_sent_actions_are_not_retried() {
+        let policy = RequestPolicy {
+            retries: 3,
+            initial_backoff: Duration::from_millis(1),
+            ..RequestPolicy::default()
+        };
+        // The first connection is refused, the next requests are carried out but time out before
+        // the gateway answers.
+        let refused = AtomicBool::new(false);
+        let transport = FnTransport::new(move |_, _| match refused.swap(true, SeqCst) {
+            false => Err(crate::TransportError::ConnectError(std::io::ErrorKind::ConnectionRefused.into())),
+            true => Err(crate::TransportError::IoError(std::io::ErrorKind::TimedOut.into())),
+        });
+        let gateway = Gateway::new("192.168.1.1:5000".parse().unwrap(), "/ctl/IPConn".to_owned(), "urn:schemas-upnp-org:service:WANIPConnection:1".to_owned())
+            .with_transport(transport.clone())
+            .with_request_policy(policy);
+        // The refused connection is retried, the remove the gateway carried out is not.
+        match crate::runtime::block_on(gateway.remove_port(PortMappingProtocol::TCP, 8080)) {
+            Err(RemovePortError::RequestError(RequestError::IoError(e))) => assert_eq!(e.kind(), std::io::ErrorKind::TimedOut),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(transport.actions(), ["DeletePortMapping", "DeletePortMapping"]);
+    }
+
+    #[test]
+    fn test_max_in_flight_requests() {
+        for limit in [None, Some(1), Some(3)] {
+            // The transport answers after a while, and records the most requests it had at the same time.
+            let (current, max) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+            let counters = (current.clone(), max.clone());
+            let transport = FnTransport::new_async(move |_, _| {
+                let (current, max) = counters.clone();
+                Box::pin(async move {
+                    max.fetch_max(current.fetch_add(1, SeqCst) + 1, SeqCst);
+                    crate::runtime::sleep(Duration::from_millis(20)).await;
+                    current.fetch_sub(1, SeqCst);
+                    Ok(external_ip_response())
+                })
+            });
+            let gateway = Gateway::new("192.168.1.1:5000".parse().unwrap(), "/ctl/IPConn".to_owned(), "urn:schemas-upnp-org:service:WANIPConnection:1".to_owned())
+                .with_transport(transport);
+            // The clones made before the limit is set follow it too.
+            let requests = (0..6).map(|_| gateway.clone()).collect::<Vec<_>>();
+            if let Some(limit) = limit {
+                let gateway = gateway.with_max_in_flight_requests(limit);
+                assert_eq!(requests[0].max_in_flight_requests(), Some(limit));
+                assert_eq!(gateway.max_in_flight_requests(), Some(limit));
+            }
+            let results = crate::runtime::block_on(future::join_all(requests.iter().map(|gateway| gateway.get_external_ip())));
+            assert!(results.iter().all(|ip| ip.as_ref().ok() == Some(&Ipv4Addr::new(203, 0, 113, 7))));
+            assert_eq!(max.load(SeqCst), limit.unwrap_or(requests.len()));
+        }
+    }
+
+    // A version 2 gateway listing two mappings, answering DeletePortMappingRange with the given
+    // error code and carrying out DeletePortMapping.
+    fn range_transport(error_code: u16) -> Arc<FnTransport> {
+        FnTransport::new(move |action, _| match action {
+            "GetListOfPortMappings" => {
+                let entry = |port| {
+                    format!(
+                        "&lt;p:PortMappingEntry&gt;&lt;p:NewExternalPort&gt;{}&lt;/p:NewExternalPort&gt;&lt;p:NewProtocol&gt;UDP&lt;/p:NewProtocol&gt;\
+                         &lt;p:NewInternalPort&gt;{}&lt;/p:NewInternalPort&gt;&lt;p:NewInternalClient&gt;192.168.1.20&lt;/p:NewInternalClient&gt;\
+                         &lt;p:NewEnabled&gt;1&lt;/p:NewEnabled&gt;&lt;/p:PortMappingEntry&gt;",
+                        port, port
+                    )
+                };
+                let listing = format!(
+                    "<NewPortListing>&lt;p:PortMappingList xmlns:p=\"urn:schemas-upnp-org:gw:WANIPConnection\"&gt;{}{}&lt;/p:PortMappingList&gt;</NewPortListing>",
+                    entry(5000),
+                    entry(5002)
+                );
+                Ok(envelope(action, &listing))
+            }
+            "DeletePortMappingRange" => Ok(fault(error_code)),
+            _ => Ok(envelope(action, "")),
+        })
+    }
+
+    #[test]
+    fn test_remove_port_range_fallback() {
+        let remove_range = |error_code| {
+            let transport = range_transport(error_code);
+            let gateway = Gateway::new("192.168.1.1:5000".parse().unwrap(), "/ctl/IPConn".to_owned(), "urn:schemas-upnp-org:service:WANIPConnection:2".to_owned())
+                .with_transport(transport.clone());
+            let report = crate::runtime::block_on(gateway.remove_port_range(PortMappingProtocol::UDP, 5000, 5010, false));
+            (report, transport.actions())
+        };
+
+        // Gateways without DeletePortMappingRange get the mappings removed one by one.
+        let (report, actions) = remove_range(401);
+        assert_eq!(report.unwrap().removed, vec![5000, 5002]);
+        assert_eq!(actions, vec!["GetListOfPortMappings", "DeletePortMappingRange", "DeletePortMapping", "DeletePortMapping"]);
+
+        // A refusal is not asked again for every mapping.
+        let (report, actions) = remove_range(606);
+        let report = report.unwrap();
+        assert!(report.removed.is_empty());
+        assert_eq!(report.failed.iter().map(|(port, _)| *port).collect::<Vec<_>>(), vec![5000, 5002]);
+        assert!(report.failed.iter().all(|(_, e)| matches!(e, RemovePortError::ActionNotAuthorized)));
+        assert_eq!(actions, vec!["GetListOfPortMappings", "DeletePortMappingRange"]);
+
+        // Other errors are returned.
+        let (report, actions) = remove_range(501);
+        match report {
+            Err(ListPortMappingsError::RequestError(RequestError::ErrorCode(501, _))) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(actions, vec!["GetListOfPortMappings", "DeletePortMappingRange"]);
+    }
+
+    #[test]
+    fn test_list_port_mappings_fallback() {
+        // A version 2 gateway without GetListOfPortMappings, with three entries in its table.
+        let transport = FnTransport::new(|action, request| match action {
+            "GetListOfPortMappings" => Ok(fault(401)),
+            "GetGenericPortMappingEntry" => {
+                let body = String::from_utf8_lossy(request.body());
+                let index = body.split("<NewPortMappingIndex>").nth(1).and_then(|rest| rest.split('<').next()).unwrap();
+                let (protocol, port) = match index {
+                    "0" => ("TCP", 5000),
+                    "1" => ("UDP", 5001),
+                    "2" => ("TCP", 6000),
+                    _ => return Ok(fault(713)),
+                };
+                let args = format!(
+                    "<NewRemoteHost></NewRemoteHost><NewExternalPort>{}</NewExternalPort><NewProtocol>{}</NewProtocol><NewInternalPort>{}</NewInternalPort>\
+                     <NewInternalClient>192.168.1.20</NewInternalClient><NewEnabled>1</NewEnabled><NewPortMappingDescription>test</NewPortMappingDescription>\
+                     <NewLeaseDuration>0</NewLeaseDuration>",
+                    port, protocol, port
+                );
+                Ok(envelope(action, &args))
+            }
+            _ => panic!("unexpected action {}", action),
+        });
+        let gateway = Gateway::new("192.168.1.1:5000".parse().unwrap(), "/ctl/IPConn".to_owned(), "urn:schemas-upnp-org:service:WANIPConnection:2".to_owned())
+            .with_transport(transport.clone());
+
+        let entries = crate::runtime::block_on(gateway.list_port_mappings(5000, 5999, PortMappingProtocol::TCP, true, 0)).unwrap();
+        assert_eq!(entries.iter().map(|entry| entry.external_port).collect::<Vec<_>>(), [5000]);
+        assert_eq!(transport.actions()[0], "GetListOfPortMappings");
+        // The whole table is read, up to the index past its end.
+        assert_eq!(transport.actions().len(), 5);
+
+        // Reading stops once max_entries are found.
+        let entries = crate::runtime::block_on(gateway.list_port_mappings(0, 65535, PortMappingProtocol::TCP, true, 1)).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(transport.actions().len(), 7);
+    }
+}
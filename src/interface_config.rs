@@ -0,0 +1,214 @@
+use std::time::{Duration, Instant};
+
+use futures::future;
+use futures::stream::{self, BoxStream};
+use futures::TryStreamExt;
+
+use crate::errors::{GetInterfaceConfigError, RequestError};
+use crate::gateway::{child_text, Idempotent};
+use crate::runtime;
+use crate::Gateway;
+
+const SERVICE_TYPE: &str = "urn:schemas-upnp-org:service:WANCommonInterfaceConfig:1";
+
+impl Gateway {
+    // Call the given action of the WANCommonInterfaceConfig service.
+    async fn perform_interface_config_action(&self, action: &str) -> Result<(String, xmltree::Element), GetInterfaceConfigError> {
+        let control_url = self.common_interface_control_url().ok_or(GetInterfaceConfigError::ServiceNotAvailable)?;
+        // The actions of the service only read it.
+        Ok(self.perform_service_action(&control_url, SERVICE_TYPE, action, "", Idempotent::Yes).await?)
+    }
+
+    // Read a counter of the WANCommonInterfaceConfig service.
+    async fn get_counter(&self, action: &str, name: &str) -> Result<u64, GetInterfaceConfigError> {
+        let (text, response) = self.perform_interface_config_action(action).await?;
+        match child_text(&response, name).and_then(|t| t.parse::<u64>().ok()) {
+            Some(counter) => Ok(counter),
+            None => Err(GetInterfaceConfigError::RequestError(RequestError::InvalidResponse(text))),
+        }
+    }
+
+    /// Get the access type, the maximum bit rates and the status of the physical WAN link of the gateway.
+    pub async fn common_link_properties(&self) -> Result<CommonLinkProperties, GetInterfaceConfigError> {
+        let (text, response) = self.perform_interface_config_action("GetCommonLinkProperties").await?;
+        let properties = (|| {
+            Some(CommonLinkProperties {
+                wan_access_type: WanAccessType::parse(child_text(&response, "NewWANAccessType")?),
+                layer1_upstream_max_bit_rate: child_text(&response, "NewLayer1UpstreamMaxBitRate")?.parse().ok()?,
+                layer1_downstream_max_bit_rate: child_text(&response, "NewLayer1DownstreamMaxBitRate")?.parse().ok()?,
+                physical_link_status: PhysicalLinkStatus::parse(child_text(&response, "NewPhysicalLinkStatus")?),
+            })
+        })();
+        properties.ok_or(GetInterfaceConfigError::RequestError(RequestError::InvalidResponse(text)))
+    }
+
+    /// Get the number of bytes sent on the WAN link of the gateway.
+    ///
+    /// Most gateways use a 32 bit counter, which wraps around.
+    pub async fn total_bytes_sent(&self) -> Result<u64, GetInterfaceConfigError> {
+        self.get_counter("GetTotalBytesSent", "NewTotalBytesSent").await
+    }
+
+    /// Get the number of bytes received on the WAN link of the gateway.
+    ///
+    /// Most gateways use a 32 bit counter, which wraps around.
+    pub async fn total_bytes_received(&self) -> Result<u64, GetInterfaceConfigError> {
+        self.get_counter("GetTotalBytesReceived", "NewTotalBytesReceived").await
+    }
+
+    /// Get the number of packets sent on the WAN link of the gateway.
+    pub async fn total_packets_sent(&self) -> Result<u64, GetInterfaceConfigError> {
+        self.get_counter("GetTotalPacketsSent", "NewTotalPacketsSent").await
+    }
+
+    /// Get the number of packets received on the WAN link of the gateway.
+    pub async fn total_packets_received(&self) -> Result<u64, GetInterfaceConfigError> {
+        self.get_counter("GetTotalPacketsReceived", "NewTotalPacketsReceived").await
+    }
+
+    /// Poll the byte counters of the gateway at the given interval and report the throughput of the WAN link.
+    ///
+    /// Every item covers the traffic since the previous poll, the first one is yielded after two polls.
+    /// A failed poll yields its error and the stream goes on.
+    pub fn throughput(&self, interval: Duration) -> BoxStream<'static, Result<Throughput, GetInterfaceConfigError>> {
+        let gateway = self.clone();
+        let mut previous: Option<(Instant, u64, u64)> = None;
+        let ticks = runtime::interval(interval);
+        let stream = stream::unfold(ticks, move |mut ticks| {
+            let gateway = gateway.clone();
+            async move {
+                ticks.tick().await;
+                let counters = future::try_join(gateway.total_bytes_sent(), gateway.total_bytes_received()).await;
+                Some((counters.map(|(sent, received)| (Instant::now(), sent, received)), ticks))
+            }
+        })
+        .try_filter_map(move |(now, sent, received)| {
+            let throughput = previous.map(|(then, previous_sent, previous_received)| Throughput {
+                bytes_sent: counter_delta(previous_sent, sent),
+                bytes_received: counter_delta(previous_received, received),
+                elapsed: now - then,
+            });
+            previous = Some((now, sent, received));
+            future::ready(Ok(throughput))
+        });
+        Box::pin(stream)
+    }
+}
+
+// Difference between two readings of a counter, accounting for 32 bit counters wrapping around.
+fn counter_delta(previous: u64, current: u64) -> u64 {
+    if current >= previous {
+        current - previous
+    } else if previous <= u64::from(u32::MAX) {
+        current + (u64::from(u32::MAX) + 1 - previous)
+    } else {
+        // A wider counter went backwards, the gateway reset it.
+        current
+    }
+}
+
+/// The properties of the WAN link of a gateway, as returned by `Gateway::common_link_properties`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CommonLinkProperties {
+    /// The type of the WAN link.
+    pub wan_access_type: WanAccessType,
+    /// The maximum upstream bit rate of the physical link.
+    pub layer1_upstream_max_bit_rate: u32,
+    /// The maximum downstream bit rate of the physical link.
+    pub layer1_downstream_max_bit_rate: u32,
+    /// The status of the physical link.
+    pub physical_link_status: PhysicalLinkStatus,
+}
+
+/// The type of the WAN link of a gateway.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WanAccessType {
+    /// Digital subscriber line.
+    Dsl,
+    /// Analog phone line.
+    Pots,
+    /// Cable modem.
+    Cable,
+    /// Ethernet.
+    Ethernet,
+    /// A type not defined by the specification.
+    Other(String),
+}
+
+impl WanAccessType {
+    fn parse(text: &str) -> WanAccessType {
+        match text {
+            "DSL" => WanAccessType::Dsl,
+            "POTS" => WanAccessType::Pots,
+            "Cable" => WanAccessType::Cable,
+            "Ethernet" => WanAccessType::Ethernet,
+            other => WanAccessType::Other(other.to_owned()),
+        }
+    }
+}
+
+/// The status of the physical WAN link of a gateway.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PhysicalLinkStatus {
+    /// The link is up.
+    Up,
+    /// The link is down.
+    Down,
+    /// The link is being brought up.
+    Initializing,
+    /// The link is not available.
+    Unavailable,
+    /// A status not defined by the specification.
+    Other(String),
+}
+
+impl PhysicalLinkStatus {
+    fn parse(text: &str) -> PhysicalLinkStatus {
+        match text {
+            "Up" => PhysicalLinkStatus::Up,
+            "Down" => PhysicalLinkStatus::Down,
+            "Initializing" => PhysicalLinkStatus::Initializing,
+            "Unavailable" => PhysicalLinkStatus::Unavailable,
+            other => PhysicalLinkStatus::Other(other.to_owned()),
+        }
+    }
+}
+
+/// The traffic on the WAN link of a gateway between two polls, as yielded by `Gateway::throughput`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Throughput {
+    /// The number of bytes sent.
+    pub bytes_sent: u64,
+    /// The number of bytes received.
+    pub bytes_received: u64,
+    /// The time between the two polls.
+    pub elapsed: Duration,
+}
+
+impl Throughput {
+    /// The upstream throughput in bytes per second.
+    pub fn upstream(&self) -> f64 {
+        self.bytes_sent as f64 / self.elapsed_secs()
+    }
+
+    /// The downstream throughput in bytes per second.
+    pub fn downstream(&self) -> f64 {
+        self.bytes_received as f64 / self.elapsed_secs()
+    }
+
+    fn elapsed_secs(&self) -> f64 {
+        self.elapsed.as_secs() as f64 + f64::from(self.elapsed.subsec_nanos()) / 1e9
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counter_delta() {
+        assert_eq!(counter_delta(100, 250), 150);
+        assert_eq!(counter_delta(4_294_967_200, 100), 196);
+        assert_eq!(counter_delta(10_000_000_000, 5), 5);
+    }
+}
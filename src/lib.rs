@@ -1,43 +1,80 @@
 //! This library allows you to communicate with an IGD enabled device.
 //! Use one of the `search_gateway` functions to obtain a `Gateway` object.
 //! You can then communicate with the device via this object.
+//!
+//! The futures and streams returned by `Gateway` and by the search functions are `Send`, so that
+//! they can be spawned on multi-threaded runtimes.
 
 #![deny(missing_docs)]
 
 extern crate futures;
+extern crate bytes;
+extern crate http_body_util;
 extern crate hyper;
 extern crate mime;
 extern crate rand;
 extern crate regex;
-extern crate tokio;
-extern crate tokio_retry;
 extern crate xml;
 extern crate xmltree;
 #[macro_use] extern crate failure;
 
 // data structures
-pub use self::errors::{AddAnyPortError, AddPortError, GetExternalIpError, RemovePortError, RequestError, SearchError};
-pub use self::gateway::Gateway;
+pub use self::errors::{AddAnyPortError, AddPortError, GetConnectionInfoError, GetExternalIpError, GetGenericPortMappingEntryError, GetInterfaceConfigError, GetPortMappingError, GetStatusInfoError, ListPortMappingsError, NatPmpError, PcpError, PinholeError, RemovePortError, RequestError, SearchError, SubscribeError, TransportError};
+pub use self::gateway::{
+    ConnectionStatus, ConnectionType, ConnectionTypeInfo, Gateway, LastConnectionError, NatRsipStatus, PortMappingEntry, RemovePortRangeReport, StatusInfo,
+};
+pub use self::events::{GatewayEvent, GatewayEvents, Subscription};
+pub use self::firewall::FirewallStatus;
+pub use self::interface_config::{CommonLinkProperties, PhysicalLinkStatus, Throughput, WanAccessType};
+pub use self::manager::{MappingEvent, MappingEvents, MappingManager};
+pub use self::mapper::{AutoMapper, MappedPort, MappingMechanism, PortMapper};
+pub use self::mapping::{remove_all_port_mappings, remove_all_port_mappings_on_shutdown, PortMapping};
+pub use self::natpmp::{NatPmpAnnouncement, NatPmpGateway, NatPmpMapping};
+pub use self::pcp::{PcpGateway, PcpMapping, PcpOptions};
+pub use self::policy::RequestPolicy;
+pub use self::transport::{HyperTransport, Transport};
 
 // search of gateway
 pub use self::search::search_gateway;
 pub use self::search::search_gateway_from;
 pub use self::search::search_gateway_from_timeout;
 pub use self::search::search_gateway_timeout;
+pub use self::search::search_gateway_with_options;
+pub use self::search::search_gateway_with_transport;
+pub use self::search::search_gateways;
+pub use self::search::search_gateways_from;
+pub use self::search::search_gateways_from_timeout;
+pub use self::search::search_gateways_timeout;
+pub use self::search::search_gateways_with_options;
+pub use self::search::search_gateways_with_transport;
+pub use self::search::SearchOptions;
 
 // re-export error types
 pub use hyper::Error as HttpError;
 pub use xml::reader::Error as XmlError;
 
 mod errors;
+mod events;
+mod firewall;
 mod gateway;
+mod interface_config;
+mod manager;
+mod mapper;
+mod mapping;
+mod natpmp;
+mod pcp;
+mod policy;
+mod runtime;
 mod search;
 mod soap;
+#[cfg(feature = "sync")]
+pub mod sync;
+mod transport;
 
 use std::fmt;
 
 /// Represents the protocols available for port mapping.
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum PortMappingProtocol {
     /// TCP protocol
     TCP,
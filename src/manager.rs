@@ -0,0 +1,429 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddrV4;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+use async_lock::Mutex as AsyncMutex;
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::future;
+use futures::stream;
+use futures::{StreamExt, TryStreamExt};
+use rand::Rng;
+
+use crate::errors::{AddAnyPortError, AddPortError, GetPortMappingError, RemovePortError, SearchError};
+use crate::runtime;
+use crate::search::{self, Announcement};
+use crate::{Gateway, PortMappingProtocol};
+
+// How often mappings with an infinite lease are checked for.
+const CHECK_INTERVAL: Duration = Duration::from_secs(300);
+// How long to wait before trying again to renew a mapping after a failure.
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+// How far below the expected value the uptime of the gateway may be before it counts as a reset.
+const UPTIME_TOLERANCE: Duration = Duration::from_secs(10);
+
+/// The receiving end of the events of a `MappingManager`.
+pub type MappingEvents = UnboundedReceiver<MappingEvent>;
+
+/// Events reported by a `MappingManager` while it keeps its mappings alive.
+#[derive(Debug)]
+pub enum MappingEvent {
+    /// The lease of a mapping was renewed.
+    Renewed {
+        /// The protocol of the mapping.
+        protocol: PortMappingProtocol,
+        /// The external port of the mapping.
+        external_port: u16,
+    },
+    /// A mapping had vanished from the gateway and was added again.
+    Recreated {
+        /// The protocol of the mapping.
+        protocol: PortMappingProtocol,
+        /// The external port of the mapping.
+        external_port: u16,
+    },
+    /// A mapping could not be renewed. The manager tries again later.
+    RenewFailed {
+        /// The protocol of the mapping.
+        protocol: PortMappingProtocol,
+        /// The external port of the mapping.
+        external_port: u16,
+        /// The error returned by the gateway.
+        error: AddPortError,
+    },
+    /// The gateway rebooted. Every mapping of the manager is added again and reported with a
+    /// `Recreated` or a `RenewFailed` event.
+    GatewayRebooted,
+}
+
+/// Keeps a set of port mappings alive on a gateway.
+///
+/// Every mapping added through the manager is renewed before its lease expires, at about half
+/// of the lease with some jitter so that many clients do not hit the gateway at once. Mappings
+/// that vanish from the gateway, for instance after a reboot, are added again. Mappings with an
+/// infinite lease are checked for every 5 minutes. Use `watch_reboots` to restore the mappings as
+/// soon as the gateway reboots.
+///
+/// The renewals run as tasks on the runtime the mappings are added from. They stop when
+/// the mapping is removed through the manager or when every clone of the manager is dropped.
+#[derive(Clone)]
+pub struct MappingManager {
+    gateway: Gateway,
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    mappings: HashMap<(PortMappingProtocol, u16), Mapping>,
+    next_generation: u64,
+    events: UnboundedSender<MappingEvent>,
+}
+
+#[derive(Clone)]
+struct Mapping {
+    local_addr: SocketAddrV4,
+    lease_duration: u32,
+    description: String,
+    // Tells the renewal task of a mapping apart from the one of a previous mapping of the same port.
+    generation: u64,
+    // Held while the mapping is renewed or removed, so that a renewal never adds the mapping back
+    // after its removal.
+    busy: Arc<AsyncMutex<()>>,
+}
+
+impl MappingManager {
+    /// Create a manager for the mappings of the given gateway, and the stream of its events.
+    pub fn new(gateway: Gateway) -> (MappingManager, MappingEvents) {
+        let (events, receiver) = mpsc::unbounded();
+        let inner = Inner {
+            mappings: HashMap::new(),
+            next_generation: 0,
+            events,
+        };
+        let manager = MappingManager {
+            gateway,
+            inner: Arc::new(Mutex::new(inner)),
+        };
+        (manager, receiver)
+    }
+
+    /// get the gateway of the manager
+    pub fn gateway(&self) -> &Gateway {
+        &self.gateway
+    }
+
+    /// Add a port mapping and keep it alive.
+    ///
+    /// The local_addr is the address where the traffic is sent to.
+    /// The lease_duration parameter is in seconds. A value of 0 is infinite.
+    pub async fn add_port(&self, protocol: PortMappingProtocol, external_port: u16, local_addr: SocketAddrV4, lease_duration: u32, description: &str) -> Result<(), AddPortError> {
+        self.gateway.add_port(protocol, external_port, local_addr, lease_duration, description).await?;
+        self.register(protocol, external_port, local_addr, lease_duration, description.to_owned());
+        Ok(())
+    }
+
+    /// Add a port mapping with any external port and keep it alive.
+    ///
+    /// The local_addr is the address where the traffic is sent to.
+    /// The lease_duration parameter is in seconds. A value of 0 is infinite.
+    ///
+    /// # Returns
+    ///
+    /// The external port that was mapped on success. Otherwise an error.
+    pub async fn add_any_port(&self, protocol: PortMappingProtocol, local_addr: SocketAddrV4, lease_duration: u32, description: &str) -> Result<u16, AddAnyPortError> {
+        let external_port = self.gateway.add_any_port(protocol, local_addr, lease_duration, description).await?;
+        self.register(protocol, external_port, local_addr, lease_duration, description.to_owned());
+        Ok(external_port)
+    }
+
+    /// Stop keeping a port mapping alive and remove it.
+    pub async fn remove_port(&self, protocol: PortMappingProtocol, external_port: u16) -> Result<(), RemovePortError> {
+        let mapping = self.inner.lock().unwrap().mappings.remove(&(protocol, external_port));
+        // Wait for a renewal in progress, the next ones see that the mapping is gone.
+        let _busy = match &mapping {
+            Some(mapping) => Some(mapping.busy.lock().await),
+            None => None,
+        };
+        self.gateway.remove_port(protocol, external_port).await
+    }
+
+    /// get the protocol and external port of every mapping kept alive by the manager
+    pub fn mappings(&self) -> Vec<(PortMappingProtocol, u16)> {
+        self.inner.lock().unwrap().mappings.keys().cloned().collect()
+    }
+
+    /// Watch the gateway for reboots and add the mappings of the manager again when it reboots.
+    ///
+    /// Gateways lose their mappings when they reboot. A reboot is detected when the gateway
+    /// announces a new boot id over SSDP, or when the uptime returned by `Gateway::status_info`,
+    /// polled at the given interval, goes back. That uptime is the one of the WAN connection, so a
+    /// reconnection is taken for a reboot too, which only renews the mappings early.
+    ///
+    /// The future does not borrow the manager and can be spawned. It runs until every clone of the
+    /// manager is dropped. It fails if the SSDP announcements can not be listened for.
+    pub fn watch_reboots(&self, poll_interval: Duration) -> impl Future<Output = Result<(), SearchError>> + Send + 'static {
+        let gateway = self.gateway.clone();
+        let ip = gateway.ip_addr();
+        let mut boot_id = gateway.boot_id();
+        let announced = search::announcements(ip)
+            .try_filter(move |announcement| future::ready(announcement.addr() == ip))
+            .map_ok(move |announcement| match announcement {
+                Announcement::Alive { boot_id: Some(new), .. } => {
+                    let rebooted = boot_id.is_some_and(|old| old != new);
+                    boot_id = Some(new);
+                    rebooted
+                }
+                // The boot id changes without a reboot, the next alive announcement carries the new one.
+                Announcement::Update { next_boot_id: Some(next), .. } => {
+                    boot_id = Some(next);
+                    false
+                }
+                _ => false,
+            });
+
+        let status_gateway = gateway.clone();
+        let mut previous: Option<(Instant, Duration)> = None;
+        let ticks = runtime::interval(poll_interval);
+        let uptime_reset = stream::unfold(ticks, move |mut ticks| {
+            let gateway = status_gateway.clone();
+            async move {
+                ticks.tick().await;
+                // The gateway does not answer while it reboots, skip the reading.
+                let reading = gateway.status_info().await.ok().map(|info| (Instant::now(), info.uptime));
+                Some((reading, ticks))
+            }
+        })
+        .map(move |reading| match reading {
+            Some((now, uptime)) => {
+                let reset = previous.is_some_and(|(then, previous_uptime)| uptime_went_back(previous_uptime, now - then, uptime));
+                previous = Some((now, uptime));
+                Ok(reset)
+            }
+            None => Ok(false),
+        });
+
+        let inner = Arc::downgrade(&self.inner);
+        async move {
+            let mut reboots = Box::pin(stream::select(announced, uptime_reset));
+            let mut last_restore: Option<Instant> = None;
+            while let Some(rebooted) = reboots.try_next().await? {
+                if inner.upgrade().is_none() {
+                    break;
+                }
+                // Both the announcements and the uptime report the same reboot.
+                if !rebooted || last_restore.is_some_and(|then| then.elapsed() < poll_interval) {
+                    continue;
+                }
+                last_restore = Some(Instant::now());
+                restore(&gateway, &inner).await;
+            }
+            Ok(())
+        }
+    }
+
+    fn register(&self, protocol: PortMappingProtocol, external_port: u16, local_addr: SocketAddrV4, lease_duration: u32, description: String) {
+        let key = (protocol, external_port);
+        let generation = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.next_generation += 1;
+            let generation = inner.next_generation;
+            inner.mappings.insert(
+                key,
+                Mapping {
+                    local_addr,
+                    lease_duration,
+                    description,
+                    generation,
+                    busy: Arc::new(AsyncMutex::new(())),
+                },
+            );
+            generation
+        };
+        schedule_renewal(self.gateway.clone(), Arc::downgrade(&self.inner), key, generation, renew_delay(lease_duration));
+    }
+}
+
+fn schedule_renewal(gateway: Gateway, inner: Weak<Mutex<Inner>>, key: (PortMappingProtocol, u16), generation: u64, delay: Duration) {
+    runtime::spawn(async move {
+        let mut delay = delay;
+        loop {
+            runtime::sleep(delay).await;
+            match renew(&gateway, &inner, key, generation).await {
+                Some(next) => delay = next,
+                None => return,
+            }
+        }
+    });
+}
+
+// The mapping of the given generation, unless it was removed or the manager dropped. Checked
+// again once the lock of the mapping is taken, as it may have been removed while waiting.
+fn managed(inner: &Weak<Mutex<Inner>>, key: (PortMappingProtocol, u16), generation: u64) -> Option<Mapping> {
+    inner
+        .upgrade()
+        .and_then(|inner| inner.lock().unwrap().mappings.get(&key).cloned())
+        .filter(|mapping| mapping.generation == generation)
+}
+
+// Renew a mapping, returns when to renew it next or None if it is no longer managed.
+async fn renew(gateway: &Gateway, inner: &Weak<Mutex<Inner>>, key: (PortMappingProtocol, u16), generation: u64) -> Option<Duration> {
+    let busy = managed(inner, key, generation)?.busy;
+    let _busy = busy.lock().await;
+    let mapping = managed(inner, key, generation)?;
+
+    let (protocol, external_port) = key;
+    let vanished = match gateway.get_port_mapping(protocol, external_port, "").await {
+        Ok(entry) => entry.internal_client != mapping.local_addr.ip().to_string() || entry.internal_port != mapping.local_addr.port(),
+        Err(GetPortMappingError::NoSuchEntryInArray) => true,
+        // The gateway can not tell, renew the mapping anyway.
+        Err(_) => false,
+    };
+    if !vanished && mapping.lease_duration == 0 {
+        return Some(renew_delay(mapping.lease_duration));
+    }
+    let event = match gateway.add_port(protocol, external_port, mapping.local_addr, mapping.lease_duration, &mapping.description).await {
+        Ok(()) if vanished => MappingEvent::Recreated { protocol, external_port },
+        Ok(()) => MappingEvent::Renewed { protocol, external_port },
+        Err(error) => MappingEvent::RenewFailed { protocol, external_port, error },
+    };
+    let mut delay = renew_delay(mapping.lease_duration);
+    if let MappingEvent::RenewFailed { .. } = event {
+        delay = delay.min(RETRY_INTERVAL);
+    }
+    if let Some(inner) = inner.upgrade() {
+        let _ = inner.lock().unwrap().events.unbounded_send(event);
+    }
+    Some(delay)
+}
+
+// Add every mapping again after a reboot of the gateway.
+async fn restore(gateway: &Gateway, inner: &Weak<Mutex<Inner>>) {
+    let (mappings, events) = match inner.upgrade() {
+        Some(inner) => {
+            let inner = inner.lock().unwrap();
+            let mappings = inner.mappings.iter().map(|(key, mapping)| (*key, mapping.clone())).collect::<Vec<_>>();
+            (mappings, inner.events.clone())
+        }
+        None => return,
+    };
+    let _ = events.unbounded_send(MappingEvent::GatewayRebooted);
+
+    let additions = mappings.into_iter().map(|((protocol, external_port), mapping)| {
+        let events = events.clone();
+        async move {
+            let _busy = mapping.busy.lock().await;
+            // The mapping may have been removed while waiting.
+            if managed(inner, (protocol, external_port), mapping.generation).is_none() {
+                return;
+            }
+            let event = match gateway.add_port(protocol, external_port, mapping.local_addr, mapping.lease_duration, &mapping.description).await {
+                Ok(()) => MappingEvent::Recreated { protocol, external_port },
+                Err(error) => MappingEvent::RenewFailed { protocol, external_port, error },
+            };
+            let _ = events.unbounded_send(event);
+        }
+    });
+    future::join_all(additions).await;
+}
+
+// Whether the uptime read after the given time is lower than the previous reading allows.
+fn uptime_went_back(previous: Duration, elapsed: Duration, uptime: Duration) -> bool {
+    uptime + UPTIME_TOLERANCE < previous + elapsed
+}
+
+// Renew at about half of the lease, give or take a tenth of it.
+fn renew_delay(lease_duration: u32) -> Duration {
+    if lease_duration == 0 {
+        return CHECK_INTERVAL;
+    }
+    let lease = u64::from(lease_duration) * 1000;
+    let jitter = lease / 10;
+    let delay = if jitter > 0 {
+        lease / 2 - jitter + rand::thread_rng().gen_range(0, 2 * jitter)
+    } else {
+        lease / 2
+    };
+    Duration::from_millis(delay.max(1000))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::test::{envelope, fault, FnTransport};
+    use futures::channel::oneshot;
+    use futures::FutureExt;
+    use std::pin::pin;
+
+    #[test]
+    fn test_renew_delay() {
+        for _ in 0..100 {
+            let delay = renew_delay(3600);
+            assert!(delay >= Duration::from_secs(1440) && delay < Duration::from_secs(2160));
+        }
+        assert_eq!(renew_delay(1), Duration::from_secs(1));
+        assert_eq!(renew_delay(0), CHECK_INTERVAL);
+    }
+
+    #[test]
+    fn test_uptime_went_back() {
+        let minute = Duration::from_secs(60);
+        assert!(!uptime_went_back(Duration::from_secs(3600), minute, Duration::from_secs(3660)));
+        assert!(!uptime_went_back(Duration::from_secs(3600), minute, Duration::from_secs(3655)));
+        assert!(uptime_went_back(Duration::from_secs(3600), minute, Duration::from_secs(30)));
+        // A gateway that rebooted just after the previous reading has not caught up with it yet.
+        assert!(uptime_went_back(Duration::from_secs(20), minute, Duration::from_secs(55)));
+    }
+
+    // A gateway without the mappings it is asked for. It tells when it receives the first
+    // GetSpecificPortMappingEntry, and answers it once released.
+    fn held_gateway() -> (Arc<FnTransport>, oneshot::Receiver<()>, oneshot::Sender<()>) {
+        let (started_tx, started_rx) = oneshot::channel();
+        let (release_tx, release_rx) = oneshot::channel();
+        let channels = Mutex::new((Some(started_tx), Some(release_rx)));
+        let transport = FnTransport::new_async(move |action, _| {
+            if action != "GetSpecificPortMappingEntry" {
+                return future::ok(envelope(action, "")).boxed();
+            }
+            let (started, release) = &mut *channels.lock().unwrap();
+            if let Some(started) = started.take() {
+                let _ = started.send(());
+            }
+            let release = release.take();
+            Box::pin(async move {
+                if let Some(release) = release {
+                    let _ = release.await;
+                }
+                Ok(fault(714))
+            })
+        });
+        (transport, started_rx, release_tx)
+    }
+
+    #[test]
+    fn test_remove_waits_for_renewal() {
+        let (transport, started, release) = held_gateway();
+        let gateway = Gateway::new("192.168.1.1:5000".parse().unwrap(), "/ctl/IPConn".to_owned(), "urn:schemas-upnp-org:service:WANIPConnection:1".to_owned())
+            .with_transport(transport.clone());
+        let (manager, _events) = MappingManager::new(gateway.clone());
+        manager.register(PortMappingProtocol::TCP, 8080, "192.168.1.20:8080".parse().unwrap(), 3600, "test".to_owned());
+        let inner = Arc::downgrade(&manager.inner);
+        let key = (PortMappingProtocol::TCP, 8080);
+
+        // The removal starts while the renewal checks for the mapping, which it finds vanished.
+        let remove = async {
+            started.await.unwrap();
+            let mut removal = pin!(manager.remove_port(PortMappingProtocol::TCP, 8080));
+            assert!(futures::poll!(removal.as_mut()).is_pending());
+            release.send(()).unwrap();
+            removal.await
+        };
+        let (renewed, removed) = runtime::block_on(future::join(renew(&gateway, &inner, key, 1), remove));
+        assert!(renewed.is_some());
+        removed.unwrap();
+        assert_eq!(transport.actions(), ["GetSpecificPortMappingEntry", "AddPortMapping", "DeletePortMapping"]);
+
+        // Once removed, the mapping is no longer renewed.
+        assert!(runtime::block_on(renew(&gateway, &inner, key, 1)).is_none());
+        assert_eq!(transport.actions().len(), 3);
+    }
+}
@@ -0,0 +1,402 @@
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+
+use crate::errors::{AddAnyPortError, AddPortError, GetExternalIpError, PcpError, RemovePortError, RequestError, SearchError};
+use crate::runtime;
+use crate::{search_gateway_timeout, Gateway, NatPmpGateway, PcpGateway, PcpMapping, PcpOptions, PortMappingProtocol};
+
+// The lease asked for by NAT-PMP and PCP when an infinite one is wanted, as recommended by RFC 6886.
+const DEFAULT_LEASE_DURATION: u32 = 7200;
+// The port of the short-lived PCP mapping used to learn the external address, the discard port.
+const PROBE_PORT: u16 = 9;
+
+/// The protocol used by a `PortMapper`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MappingMechanism {
+    /// UPnP IGD, through a `Gateway`.
+    Upnp,
+    /// The Port Control Protocol, through a `PcpGateway`.
+    Pcp,
+    /// NAT-PMP, through a `NatPmpGateway`.
+    NatPmp,
+}
+
+impl fmt::Display for MappingMechanism {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MappingMechanism::Upnp => write!(f, "UPnP"),
+            MappingMechanism::Pcp => write!(f, "PCP"),
+            MappingMechanism::NatPmp => write!(f, "NAT-PMP"),
+        }
+    }
+}
+
+/// A port mapping created by a `PortMapper`.
+///
+/// Keep it to renew or remove the mapping with the mapper that created it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MappedPort {
+    /// The protocol that created the mapping.
+    pub mechanism: MappingMechanism,
+    /// The protocol of the mapping.
+    pub protocol: PortMappingProtocol,
+    /// The address the traffic is sent to.
+    pub local_addr: SocketAddrV4,
+    /// The external port of the mapping.
+    pub external_port: u16,
+    /// The lease of the mapping in seconds, 0 if infinite.
+    pub lease_duration: u32,
+    description: String,
+    pcp: Option<PcpMapping>,
+}
+
+/// A way of opening ports on a gateway, whatever the protocol.
+///
+/// The errors are the ones of the `Gateway` methods. The errors of NAT-PMP and PCP that have no
+/// counterpart there are reported as `RequestError::NatPmpError` and `RequestError::PcpError`.
+pub trait PortMapper: Send + Sync {
+    /// get the protocol used by the mapper
+    fn mechanism(&self) -> MappingMechanism;
+
+    /// Get the external IP address of the gateway.
+    ///
+    /// PCP has no request for it. A `PcpGateway` returns the external address of the last mapping
+    /// it was granted, and before the first one creates a UDP mapping of port 9 with a one second
+    /// lease and removes it to learn the address.
+    fn external_ip(&self) -> BoxFuture<'_, Result<Ipv4Addr, GetExternalIpError>>;
+
+    /// Check that the gateway answers, without changing anything on it.
+    ///
+    /// The default asks for the external address. A `PcpGateway` sends an ANNOUNCE request
+    /// instead, which creates no mapping.
+    fn ping(&self) -> BoxFuture<'_, Result<(), GetExternalIpError>> {
+        self.external_ip().map(|result| result.map(|_| ())).boxed()
+    }
+
+    /// Add a port mapping, with the given external port if possible and any port otherwise.
+    ///
+    /// An external_port of 0 asks for any port. The local_addr is the address where the traffic
+    /// is sent to. The lease_duration parameter is in seconds, a value of 0 asks for an infinite
+    /// lease where the protocol allows it and for 7200 seconds otherwise.
+    fn map_port<'a>(&'a self, protocol: PortMappingProtocol, external_port: u16, local_addr: SocketAddrV4, lease_duration: u32, description: &'a str) -> BoxFuture<'a, Result<MappedPort, AddAnyPortError>>;
+
+    /// Remove a port mapping.
+    fn unmap_port<'a>(&'a self, mapping: &'a MappedPort) -> BoxFuture<'a, Result<(), RemovePortError>>;
+
+    /// Renew the lease of a port mapping, keeping its external port.
+    fn renew_port<'a>(&'a self, mapping: &'a MappedPort) -> BoxFuture<'a, Result<MappedPort, AddAnyPortError>>;
+}
+
+impl PortMapper for Gateway {
+    fn mechanism(&self) -> MappingMechanism {
+        MappingMechanism::Upnp
+    }
+
+    fn external_ip(&self) -> BoxFuture<'_, Result<Ipv4Addr, GetExternalIpError>> {
+        self.get_external_ip().boxed()
+    }
+
+    fn map_port<'a>(&'a self, protocol: PortMappingProtocol, external_port: u16, local_addr: SocketAddrV4, lease_duration: u32, description: &'a str) -> BoxFuture<'a, Result<MappedPort, AddAnyPortError>> {
+        async move {
+            let external_port = if external_port == 0 {
+                self.add_any_port(protocol, local_addr, lease_duration, description).await?
+            } else {
+                match self.add_port(protocol, external_port, local_addr, lease_duration, description).await {
+                    Ok(()) => external_port,
+                    // Only another port can help, other errors would come back from any port.
+                    Err(AddPortError::PortInUse) => self.add_any_port(protocol, local_addr, lease_duration, description).await?,
+                    Err(e) => return Err(e.into()),
+                }
+            };
+            Ok(MappedPort {
+                mechanism: MappingMechanism::Upnp,
+                protocol,
+                local_addr,
+                external_port,
+                lease_duration,
+                description: description.to_owned(),
+                pcp: None,
+            })
+        }
+        .boxed()
+    }
+
+    fn unmap_port<'a>(&'a self, mapping: &'a MappedPort) -> BoxFuture<'a, Result<(), RemovePortError>> {
+        self.remove_port(mapping.protocol, mapping.external_port).boxed()
+    }
+
+    fn renew_port<'a>(&'a self, mapping: &'a MappedPort) -> BoxFuture<'a, Result<MappedPort, AddAnyPortError>> {
+        async move {
+            self.add_port(mapping.protocol, mapping.external_port, mapping.local_addr, mapping.lease_duration, &mapping.description)
+                .await?;
+            Ok(mapping.clone())
+        }
+        .boxed()
+    }
+}
+
+impl PortMapper for NatPmpGateway {
+    fn mechanism(&self) -> MappingMechanism {
+        MappingMechanism::NatPmp
+    }
+
+    fn external_ip(&self) -> BoxFuture<'_, Result<Ipv4Addr, GetExternalIpError>> {
+        async move { Ok(self.get_external_ip().await?) }.boxed()
+    }
+
+    fn map_port<'a>(&'a self, protocol: PortMappingProtocol, external_port: u16, local_addr: SocketAddrV4, lease_duration: u32, description: &'a str) -> BoxFuture<'a, Result<MappedPort, AddAnyPortError>> {
+        async move {
+            let mapping = self.add_port(protocol, external_port, local_addr, finite_lease(lease_duration)).await?;
+            Ok(MappedPort {
+                mechanism: MappingMechanism::NatPmp,
+                protocol,
+                local_addr,
+                external_port: mapping.external_port,
+                lease_duration: mapping.lease_duration,
+                description: description.to_owned(),
+                pcp: None,
+            })
+        }
+        .boxed()
+    }
+
+    fn unmap_port<'a>(&'a self, mapping: &'a MappedPort) -> BoxFuture<'a, Result<(), RemovePortError>> {
+        async move { Ok(self.remove_port(mapping.protocol, mapping.local_addr).await?) }.boxed()
+    }
+
+    fn renew_port<'a>(&'a self, mapping: &'a MappedPort) -> BoxFuture<'a, Result<MappedPort, AddAnyPortError>> {
+        self.map_port(mapping.protocol, mapping.external_port, mapping.local_addr, mapping.lease_duration, &mapping.description)
+    }
+}
+
+impl PortMapper for PcpGateway {
+    fn mechanism(&self) -> MappingMechanism {
+        MappingMechanism::Pcp
+    }
+
+    // PCP has no request for the external address, take the one of the last mapping or map a
+    // port for a moment to learn it.
+    fn external_ip(&self) -> BoxFuture<'_, Result<Ipv4Addr, GetExternalIpError>> {
+        async move {
+            let ip = match self.last_external_ip() {
+                Some(ip) => ip,
+                None => {
+                    let local_addr = SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), PROBE_PORT);
+                    let mapping = self.add_port(PortMappingProtocol::UDP, 0, local_addr, 1, &PcpOptions::default()).await?;
+                    let _ = self.remove(&mapping).await;
+                    mapping.external_addr.ip()
+                }
+            };
+            match ip {
+                IpAddr::V4(ip) => Ok(ip),
+                IpAddr::V6(_) => Err(GetExternalIpError::RequestError(RequestError::PcpError(PcpError::InvalidResponse))),
+            }
+        }
+        .boxed()
+    }
+
+    fn ping(&self) -> BoxFuture<'_, Result<(), GetExternalIpError>> {
+        async move {
+            self.announce().await?;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn map_port<'a>(&'a self, protocol: PortMappingProtocol, external_port: u16, local_addr: SocketAddrV4, lease_duration: u32, description: &'a str) -> BoxFuture<'a, Result<MappedPort, AddAnyPortError>> {
+        async move {
+            let mapping = self
+                .add_port(protocol, external_port, local_addr.into(), finite_lease(lease_duration), &PcpOptions::default())
+                .await?;
+            Ok(MappedPort {
+                mechanism: MappingMechanism::Pcp,
+                protocol,
+                local_addr,
+                external_port: mapping.external_addr.port(),
+                lease_duration: mapping.lease_duration,
+                description: description.to_owned(),
+                pcp: Some(mapping),
+            })
+        }
+        .boxed()
+    }
+
+    fn unmap_port<'a>(&'a self, mapping: &'a MappedPort) -> BoxFuture<'a, Result<(), RemovePortError>> {
+        async move {
+            match mapping.pcp {
+                Some(ref pcp) => Ok(self.remove(pcp).await?),
+                None => Err(RemovePortError::NoSuchPortMapping),
+            }
+        }
+        .boxed()
+    }
+
+    fn renew_port<'a>(&'a self, mapping: &'a MappedPort) -> BoxFuture<'a, Result<MappedPort, AddAnyPortError>> {
+        let pcp = match mapping.pcp {
+            Some(ref pcp) => pcp,
+            // Not a PCP mapping, ask for the same port.
+            None => return self.map_port(mapping.protocol, mapping.external_port, mapping.local_addr, mapping.lease_duration, &mapping.description),
+        };
+        async move {
+            let pcp = self.renew(pcp, finite_lease(mapping.lease_duration)).await?;
+            let mut renewed = mapping.clone();
+            renewed.external_port = pcp.external_addr.port();
+            renewed.lease_duration = pcp.lease_duration;
+            renewed.pcp = Some(pcp);
+            Ok(renewed)
+        }
+        .boxed()
+    }
+}
+
+fn finite_lease(lease_duration: u32) -> u32 {
+    if lease_duration == 0 {
+        DEFAULT_LEASE_DURATION
+    } else {
+        lease_duration
+    }
+}
+
+/// A mapper using the first protocol that works among several.
+///
+/// Use `AutoMapper::discover` to try UPnP, PCP and NAT-PMP in that order. `mechanism` and the
+/// `MappedPort`s returned tell which one is used.
+pub struct AutoMapper {
+    mapper: Box<dyn PortMapper>,
+}
+
+impl AutoMapper {
+    /// Search for a UPnP gateway, then probe the router with the given address for PCP and NAT-PMP.
+    ///
+    /// The timeout applies to the search and to each probe. Fails if no protocol works.
+    pub async fn discover(router: Ipv4Addr, timeout: Duration) -> Result<AutoMapper, SearchError> {
+        let mut mappers: Vec<Box<dyn PortMapper>> = Vec::new();
+        if let Ok(gateway) = search_gateway_timeout(timeout).await {
+            mappers.push(Box::new(gateway));
+        }
+        mappers.push(Box::new(PcpGateway::new(router.into())));
+        mappers.push(Box::new(NatPmpGateway::new(router)));
+        AutoMapper::probe(mappers, timeout).await
+    }
+
+    /// Use the first of the given mappers that answers `PortMapper::ping` within the timeout.
+    ///
+    /// Probing creates no mapping: PCP is probed with an ANNOUNCE request, the other protocols by
+    /// asking for the external address.
+    pub async fn probe(mappers: Vec<Box<dyn PortMapper>>, timeout: Duration) -> Result<AutoMapper, SearchError> {
+        for mapper in mappers {
+            if let Ok(Ok(())) = runtime::timeout(timeout, mapper.ping()).await {
+                return Ok(AutoMapper { mapper });
+            }
+        }
+        Err(SearchError::IoError(io::Error::new(io::ErrorKind::NotFound, "no port mapping protocol is available")))
+    }
+}
+
+impl PortMapper for AutoMapper {
+    fn mechanism(&self) -> MappingMechanism {
+        self.mapper.mechanism()
+    }
+
+    fn external_ip(&self) -> BoxFuture<'_, Result<Ipv4Addr, GetExternalIpError>> {
+        self.mapper.external_ip()
+    }
+
+    fn ping(&self) -> BoxFuture<'_, Result<(), GetExternalIpError>> {
+        self.mapper.ping()
+    }
+
+    fn map_port<'a>(&'a self, protocol: PortMappingProtocol, external_port: u16, local_addr: SocketAddrV4, lease_duration: u32, description: &'a str) -> BoxFuture<'a, Result<MappedPort, AddAnyPortError>> {
+        self.mapper.map_port(protocol, external_port, local_addr, lease_duration, description)
+    }
+
+    fn unmap_port<'a>(&'a self, mapping: &'a MappedPort) -> BoxFuture<'a, Result<(), RemovePortError>> {
+        self.mapper.unmap_port(mapping)
+    }
+
+    fn renew_port<'a>(&'a self, mapping: &'a MappedPort) -> BoxFuture<'a, Result<MappedPort, AddAnyPortError>> {
+        self.mapper.renew_port(mapping)
+    }
+}
+
+impl fmt::Debug for AutoMapper {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "AutoMapper({})", self.mechanism())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::test::{fault, FnTransport};
+    use futures::future;
+
+    // A mapper that only tells whether it works.
+    struct Stub(MappingMechanism, bool);
+
+    impl PortMapper for Stub {
+        fn mechanism(&self) -> MappingMechanism {
+            self.0
+        }
+
+        fn external_ip(&self) -> BoxFuture<'_, Result<Ipv4Addr, GetExternalIpError>> {
+            panic!("probing must not ask for the external address")
+        }
+
+        fn ping(&self) -> BoxFuture<'_, Result<(), GetExternalIpError>> {
+            if self.1 {
+                future::ok(()).boxed()
+            } else {
+                future::err(GetExternalIpError::ActionNotAuthorized).boxed()
+            }
+        }
+
+        fn map_port<'a>(&'a self, _: PortMappingProtocol, _: u16, _: SocketAddrV4, _: u32, _: &'a str) -> BoxFuture<'a, Result<MappedPort, AddAnyPortError>> {
+            future::err(AddAnyPortError::ActionNotAuthorized).boxed()
+        }
+
+        fn unmap_port<'a>(&'a self, _: &'a MappedPort) -> BoxFuture<'a, Result<(), RemovePortError>> {
+            future::err(RemovePortError::ActionNotAuthorized).boxed()
+        }
+
+        fn renew_port<'a>(&'a self, _: &'a MappedPort) -> BoxFuture<'a, Result<MappedPort, AddAnyPortError>> {
+            future::err(AddAnyPortError::ActionNotAuthorized).boxed()
+        }
+    }
+
+    #[test]
+    fn test_probe_uses_first_working_mapper() {
+        let mappers: Vec<Box<dyn PortMapper>> = vec![
+            Box::new(Stub(MappingMechanism::Upnp, false)),
+            Box::new(Stub(MappingMechanism::Pcp, true)),
+            Box::new(Stub(MappingMechanism::NatPmp, true)),
+        ];
+        let mapper = runtime::block_on(AutoMapper::probe(mappers, Duration::from_secs(1))).unwrap();
+        assert_eq!(mapper.mechanism(), MappingMechanism::Pcp);
+
+        let mappers: Vec<Box<dyn PortMapper>> = vec![Box::new(Stub(MappingMechanism::Upnp, false))];
+        assert!(runtime::block_on(AutoMapper::probe(mappers, Duration::from_secs(1))).is_err());
+    }
+
+    #[test]
+    fn test_upnp_map_port_keeps_refusals() {
+        let refusals = [(606, AddAnyPortError::ActionNotAuthorized), (724, AddAnyPortError::SamePortValuesRequired)];
+        for (code, expected) in refusals {
+            let transport = FnTransport::new(move |_, _| Ok(fault(code)));
+            let gateway = Gateway::new("192.168.1.1:5000".parse().unwrap(), "/ctl/IPConn".to_owned(), "urn:schemas-upnp-org:service:WANIPConnection:1".to_owned())
+                .with_transport(transport.clone());
+            let local_addr = "192.168.1.20:8080".parse().unwrap();
+            match runtime::block_on(gateway.map_port(PortMappingProtocol::TCP, 8080, local_addr, 3600, "test")) {
+                Err(ref e) if std::mem::discriminant(e) == std::mem::discriminant(&expected) => (),
+                other => panic!("unexpected result for {}: {:?}", code, other.map(|mapping| mapping.external_port)),
+            }
+            // No other port is tried.
+            assert_eq!(transport.actions(), ["AddPortMapping"]);
+        }
+    }
+}
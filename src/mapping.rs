@@ -0,0 +1,175 @@
+use std::fmt;
+use std::io;
+use std::net::SocketAddrV4;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use futures::future;
+
+use crate::errors::{AddAnyPortError, AddPortError, RemovePortError};
+use crate::runtime;
+use crate::{Gateway, PortMappingProtocol};
+
+// The mappings whose handle has not been closed yet.
+static LIVE_MAPPINGS: Mutex<Vec<(usize, Gateway, PortMappingProtocol, u16)>> = Mutex::new(Vec::new());
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// A port mapping that is removed from the gateway when the handle is closed.
+///
+/// Use `close` to remove the mapping and learn whether that worked. Dropping the handle also
+/// removes the mapping, on a best-effort basis: the removal is spawned on the runtime the
+/// handle is dropped on and its outcome is ignored.
+///
+/// With the tokio runtime, a handle dropped outside of a runtime, or while the runtime shuts
+/// down, can not spawn the removal and the mapping is left on the gateway until its lease
+/// expires. Close the handle, or await `remove_all_port_mappings`, before leaving the runtime.
+pub struct PortMapping {
+    id: usize,
+    gateway: Gateway,
+    protocol: PortMappingProtocol,
+    external_port: u16,
+}
+
+impl PortMapping {
+    fn new(gateway: Gateway, protocol: PortMappingProtocol, external_port: u16) -> PortMapping {
+        let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+        LIVE_MAPPINGS.lock().unwrap().push((id, gateway.clone(), protocol, external_port));
+        PortMapping {
+            id,
+            gateway,
+            protocol,
+            external_port,
+        }
+    }
+
+    /// get the gateway of the mapping
+    pub fn gateway(&self) -> &Gateway {
+        &self.gateway
+    }
+
+    /// get the protocol of the mapping
+    pub fn protocol(&self) -> PortMappingProtocol {
+        self.protocol
+    }
+
+    /// get the external port of the mapping
+    pub fn external_port(&self) -> u16 {
+        self.external_port
+    }
+
+    /// Remove the mapping from the gateway.
+    pub async fn close(self) -> Result<(), RemovePortError> {
+        if unregister(self.id) {
+            self.gateway.remove_port(self.protocol, self.external_port).await
+        } else {
+            // The mapping was already removed by `remove_all_port_mappings`.
+            Ok(())
+        }
+    }
+
+    /// Drop the handle but keep the mapping on the gateway.
+    pub fn forget(self) {
+        unregister(self.id);
+    }
+}
+
+impl Drop for PortMapping {
+    // Nothing is done without a current tokio runtime, see the doc of `PortMapping`.
+    fn drop(&mut self) {
+        if unregister(self.id) {
+            let (gateway, protocol, external_port) = (self.gateway.clone(), self.protocol, self.external_port);
+            runtime::spawn(async move {
+                let _ = gateway.remove_port(protocol, external_port).await;
+            });
+        }
+    }
+}
+
+impl fmt::Debug for PortMapping {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PortMapping({} {} on {})", self.protocol, self.external_port, self.gateway)
+    }
+}
+
+// Forget about a live mapping, returns false if it was already gone.
+fn unregister(id: usize) -> bool {
+    let mut live = LIVE_MAPPINGS.lock().unwrap();
+    match live.iter().position(|&(live_id, ..)| live_id == id) {
+        Some(index) => {
+            live.remove(index);
+            true
+        }
+        None => false,
+    }
+}
+
+impl Gateway {
+    /// Add a port mapping, returning a handle that removes it when closed or dropped.
+    ///
+    /// The local_addr is the address where the traffic is sent to.
+    /// The lease_duration parameter is in seconds. A value of 0 is infinite.
+    ///
+    /// This is `add_port` with cleanup: the mapping is removed as soon as the handle is dropped,
+    /// so keep the handle for as long as the mapping is needed, or `forget` it.
+    pub async fn add_port_guarded(&self, protocol: PortMappingProtocol, external_port: u16, local_addr: SocketAddrV4, lease_duration: u32, description: &str) -> Result<PortMapping, AddPortError> {
+        self.add_port(protocol, external_port, local_addr, lease_duration, description).await?;
+        Ok(PortMapping::new(self.clone(), protocol, external_port))
+    }
+
+    /// Add a port mapping with any external port, returning a handle that removes it when closed or dropped.
+    ///
+    /// The local_addr is the address where the traffic is sent to.
+    /// The lease_duration parameter is in seconds. A value of 0 is infinite.
+    ///
+    /// The external port that was mapped is `PortMapping::external_port`.
+    pub async fn add_any_port_guarded(&self, protocol: PortMappingProtocol, local_addr: SocketAddrV4, lease_duration: u32, description: &str) -> Result<PortMapping, AddAnyPortError> {
+        let external_port = self.add_any_port(protocol, local_addr, lease_duration, description).await?;
+        Ok(PortMapping::new(self.clone(), protocol, external_port))
+    }
+}
+
+/// Remove every mapping whose `PortMapping` handle is still alive.
+///
+/// Errors are ignored, the future completes once every removal has been attempted. The handles
+/// stay valid, closing or dropping them afterwards does nothing.
+pub async fn remove_all_port_mappings() {
+    let live = LIVE_MAPPINGS.lock().unwrap().drain(..).collect::<Vec<_>>();
+    let removals = live.iter().map(|(_, gateway, protocol, external_port)| gateway.remove_port(*protocol, *external_port));
+    future::join_all(removals).await;
+}
+
+/// Wait for SIGINT or SIGTERM, then remove every mapping whose `PortMapping` handle is still alive.
+///
+/// Spawn this future, or select it with the main future of the application, and exit once it
+/// completes. Only SIGINT (Ctrl-C) is handled on platforms other than Unix.
+pub async fn remove_all_port_mappings_on_shutdown() -> io::Result<()> {
+    runtime::shutdown_signal().await?;
+    remove_all_port_mappings().await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_live(id: usize) -> bool {
+        LIVE_MAPPINGS.lock().unwrap().iter().any(|&(live_id, ..)| live_id == id)
+    }
+
+    #[test]
+    fn test_handle_unregisters_mapping() {
+        let gateway = Gateway::new("192.168.1.1:5000".parse().unwrap(), "/ctl/IPConn".to_owned(), "urn:schemas-upnp-org:service:WANIPConnection:1".to_owned());
+
+        let mapping = PortMapping::new(gateway.clone(), PortMappingProtocol::TCP, 8080);
+        let id = mapping.id;
+        assert!(is_live(id));
+        mapping.forget();
+        assert!(!is_live(id));
+
+        // Dropping outside of a runtime can not remove the mapping, but must not panic.
+        let mapping = PortMapping::new(gateway, PortMappingProtocol::UDP, 8080);
+        let id = mapping.id;
+        drop(mapping);
+        assert!(!is_live(id));
+    }
+}
@@ -0,0 +1,276 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::time::{Duration, Instant};
+
+use futures::future;
+use futures::stream::{self, BoxStream};
+use futures::TryStreamExt;
+
+use crate::errors::NatPmpError;
+use crate::runtime::{self, AsyncUdpSocket, UdpSocket};
+use crate::search::bind_reusable;
+use crate::PortMappingProtocol;
+
+// Port the gateway listens on for requests.
+const SERVER_PORT: u16 = 5351;
+// Port the gateway sends its announcements to.
+const CLIENT_PORT: u16 = 5350;
+const ALL_HOSTS: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 1);
+
+const OPCODE_EXTERNAL_ADDRESS: u8 = 0;
+const OPCODE_MAP_UDP: u8 = 1;
+const OPCODE_MAP_TCP: u8 = 2;
+// Added to the opcode of a request in the opcode of the response.
+const OPCODE_RESPONSE: u8 = 128;
+
+// A request is sent again after 250ms, then after twice as long every time, 9 times in all.
+const INITIAL_TIMEOUT: Duration = Duration::from_millis(250);
+const MAX_ATTEMPTS: u32 = 9;
+
+/// A gateway speaking NAT-PMP, as specified by RFC 6886.
+///
+/// NAT-PMP has no discovery, the gateway is the default router of the host.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct NatPmpGateway {
+    addr: SocketAddrV4,
+}
+
+/// A port mapping created by `NatPmpGateway::add_port`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NatPmpMapping {
+    /// The protocol of the mapping.
+    pub protocol: PortMappingProtocol,
+    /// The address the traffic is sent to.
+    pub local_addr: SocketAddrV4,
+    /// The external port, which may differ from the one asked for.
+    pub external_port: u16,
+    /// The lease granted by the gateway in seconds, which may differ from the one asked for.
+    pub lease_duration: u32,
+    /// The seconds since the gateway started. Going back means it lost its mappings.
+    pub epoch: u32,
+}
+
+/// A change of the external address announced by a gateway, as yielded by `NatPmpGateway::announcements`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NatPmpAnnouncement {
+    /// The new external address of the gateway.
+    pub external_ip: Ipv4Addr,
+    /// The seconds since the gateway started. Going back means it lost its mappings.
+    pub epoch: u32,
+}
+
+impl NatPmpGateway {
+    /// Create a gateway for the router with the given address.
+    pub fn new(ip: Ipv4Addr) -> NatPmpGateway {
+        NatPmpGateway {
+            addr: SocketAddrV4::new(ip, SERVER_PORT),
+        }
+    }
+
+    /// get the address the gateway receives requests on
+    pub fn addr(&self) -> SocketAddrV4 {
+        self.addr
+    }
+
+    /// Get the external IP address of the gateway.
+    pub async fn get_external_ip(&self) -> Result<Ipv4Addr, NatPmpError> {
+        let request = vec![0, OPCODE_EXTERNAL_ADDRESS];
+        let response = send_request(Ipv4Addr::UNSPECIFIED, self.addr, request).await?;
+        parse_external_address(&response).map(|announcement| announcement.external_ip)
+    }
+
+    /// Add a port mapping.
+    ///
+    /// The local_addr is the address where the traffic is sent to, it must be an address of this
+    /// host. An unspecified ip stands for the address the gateway is reached from.
+    /// The external_port is only a suggestion, 0 lets the gateway pick any port.
+    /// The lease_duration parameter is in seconds, the RFC recommends 7200.
+    ///
+    /// # Returns
+    ///
+    /// The mapping granted by the gateway on success. Otherwise an error.
+    pub async fn add_port(&self, protocol: PortMappingProtocol, external_port: u16, local_addr: SocketAddrV4, lease_duration: u32) -> Result<NatPmpMapping, NatPmpError> {
+        let request = mapping_request(protocol, local_addr.port(), external_port, lease_duration);
+        let response = send_request(*local_addr.ip(), self.addr, request).await?;
+        let (epoch, internal_port, external_port, lease_duration) = parse_mapping(&response, opcode(protocol))?;
+        Ok(NatPmpMapping {
+            protocol,
+            local_addr: SocketAddrV4::new(*local_addr.ip(), internal_port),
+            external_port,
+            lease_duration,
+            epoch,
+        })
+    }
+
+    /// Remove the port mapping of the given local address.
+    ///
+    /// NAT-PMP identifies mappings by their internal port, the local_addr must be the one the
+    /// mapping was added for.
+    pub async fn remove_port(&self, protocol: PortMappingProtocol, local_addr: SocketAddrV4) -> Result<(), NatPmpError> {
+        let request = mapping_request(protocol, local_addr.port(), 0, 0);
+        let response = send_request(*local_addr.ip(), self.addr, request).await?;
+        parse_mapping(&response, opcode(protocol)).map(|_| ())
+    }
+
+    /// Remove every port mapping of the given protocol for the given local address.
+    pub async fn remove_all_ports(&self, protocol: PortMappingProtocol, local_ip: Ipv4Addr) -> Result<(), NatPmpError> {
+        self.remove_port(protocol, SocketAddrV4::new(local_ip, 0)).await
+    }
+
+    /// Listen for the changes of the external address the gateway announces to 224.0.0.1:5350.
+    ///
+    /// Gateways also announce their address when they start, with an epoch close to 0.
+    /// Announcements sent by other hosts are ignored.
+    pub fn announcements(&self) -> BoxStream<'static, Result<NatPmpAnnouncement, NatPmpError>> {
+        let gateway = *self.addr.ip();
+        let listen = async {
+            let socket = bind_reusable(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), CLIENT_PORT))?;
+            socket.join_multicast_v4(&ALL_HOSTS, &Ipv4Addr::UNSPECIFIED)?;
+            let socket = UdpSocket::from_std(socket)?;
+            Ok::<_, io::Error>(stream::unfold(socket, |socket| async move {
+                let mut buf = [0; 16];
+                let datagram = socket.recv_from(&mut buf).await.map(|(n, from)| (buf[..n].to_vec(), from));
+                Some((datagram, socket))
+            }))
+        };
+        let stream = stream::once(listen)
+            .try_flatten()
+            .map_err(NatPmpError::from)
+            .try_filter_map(move |(datagram, from)| {
+                if from.ip() != gateway {
+                    return future::ok(None);
+                }
+                future::ok(parse_external_address(&datagram).ok())
+            });
+        Box::pin(stream)
+    }
+}
+
+fn opcode(protocol: PortMappingProtocol) -> u8 {
+    match protocol {
+        PortMappingProtocol::UDP => OPCODE_MAP_UDP,
+        PortMappingProtocol::TCP => OPCODE_MAP_TCP,
+    }
+}
+
+fn mapping_request(protocol: PortMappingProtocol, internal_port: u16, external_port: u16, lease_duration: u32) -> Vec<u8> {
+    let mut request = vec![0, opcode(protocol), 0, 0];
+    request.extend_from_slice(&internal_port.to_be_bytes());
+    request.extend_from_slice(&external_port.to_be_bytes());
+    request.extend_from_slice(&lease_duration.to_be_bytes());
+    request
+}
+
+// Check the header of a response and return its epoch.
+fn parse_header(response: &[u8], opcode: u8) -> Result<u32, NatPmpError> {
+    if response.len() < 8 || response[0] != 0 || response[1] != OPCODE_RESPONSE + opcode {
+        return Err(NatPmpError::InvalidResponse);
+    }
+    match u16::from_be_bytes([response[2], response[3]]) {
+        0 => Ok(u32::from_be_bytes([response[4], response[5], response[6], response[7]])),
+        1 => Err(NatPmpError::UnsupportedVersion),
+        2 => Err(NatPmpError::NotAuthorized),
+        3 => Err(NatPmpError::NetworkFailure),
+        4 => Err(NatPmpError::OutOfResources),
+        5 => Err(NatPmpError::UnsupportedOpcode),
+        code => Err(NatPmpError::UnknownResultCode(code)),
+    }
+}
+
+fn parse_external_address(response: &[u8]) -> Result<NatPmpAnnouncement, NatPmpError> {
+    let epoch = parse_header(response, OPCODE_EXTERNAL_ADDRESS)?;
+    if response.len() < 12 {
+        return Err(NatPmpError::InvalidResponse);
+    }
+    Ok(NatPmpAnnouncement {
+        external_ip: Ipv4Addr::new(response[8], response[9], response[10], response[11]),
+        epoch,
+    })
+}
+
+// Parse the epoch, internal port, external port and lease of a mapping response.
+fn parse_mapping(response: &[u8], opcode: u8) -> Result<(u32, u16, u16, u32), NatPmpError> {
+    let epoch = parse_header(response, opcode)?;
+    if response.len() < 16 {
+        return Err(NatPmpError::InvalidResponse);
+    }
+    Ok((
+        epoch,
+        u16::from_be_bytes([response[8], response[9]]),
+        u16::from_be_bytes([response[10], response[11]]),
+        u32::from_be_bytes([response[12], response[13], response[14], response[15]]),
+    ))
+}
+
+// Send the request on a socket connected to the server, again after each of the given timeouts
+// until the server answers it. Answers of other kinds are dropped.
+pub(crate) async fn transaction(socket: &UdpSocket, request: &[u8], timeouts: &[Duration], is_response: fn(&[u8], &[u8]) -> bool) -> io::Result<Vec<u8>> {
+    let mut buf = [0; 1100];
+    for timeout in timeouts {
+        socket.send(request).await?;
+        let deadline = Instant::now() + *timeout;
+        while let Ok(received) = runtime::timeout_at(deadline, socket.recv(&mut buf)).await {
+            let n = received?;
+            if is_response(request, &buf[..n]) {
+                return Ok(buf[..n].to_vec());
+            }
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::TimedOut, "the gateway did not answer"))
+}
+
+// Bind a socket to the given local address and connect it to the server, so that only the server can answer.
+pub(crate) async fn connect(local_ip: IpAddr, server: SocketAddr) -> io::Result<UdpSocket> {
+    let socket = UdpSocket::bind(SocketAddr::new(local_ip, 0)).await?;
+    socket.connect(server).await?;
+    Ok(socket)
+}
+
+// Send a NAT-PMP request on the schedule of the RFC.
+async fn send_request(local_ip: Ipv4Addr, gateway: SocketAddrV4, request: Vec<u8>) -> Result<Vec<u8>, NatPmpError> {
+    let socket = connect(local_ip.into(), gateway.into()).await?;
+    let timeouts = (0..MAX_ATTEMPTS).map(|attempt| INITIAL_TIMEOUT * 2u32.pow(attempt)).collect::<Vec<_>>();
+    Ok(transaction(&socket, &request, &timeouts, is_response).await?)
+}
+
+// Responses to an earlier request of another kind are dropped.
+fn is_response(request: &[u8], response: &[u8]) -> bool {
+    response.len() >= 2 && response[1] == OPCODE_RESPONSE + request[1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mapping_request() {
+        assert_eq!(
+            mapping_request(PortMappingProtocol::TCP, 8080, 80, 7200),
+            vec![0, 2, 0, 0, 0x1f, 0x90, 0, 80, 0, 0, 0x1c, 0x20]
+        );
+    }
+
+    #[test]
+    fn test_parse_responses() {
+        let response = [0, 128, 0, 0, 0, 0, 1, 0, 203, 0, 113, 7];
+        assert_eq!(
+            parse_external_address(&response).unwrap(),
+            NatPmpAnnouncement {
+                external_ip: Ipv4Addr::new(203, 0, 113, 7),
+                epoch: 256,
+            }
+        );
+
+        let response = [0, 129, 0, 0, 0, 0, 0, 42, 0x1f, 0x90, 0x1f, 0x91, 0, 0, 0x0e, 0x10];
+        assert_eq!(parse_mapping(&response, OPCODE_MAP_UDP).unwrap(), (42, 8080, 8081, 3600));
+
+        match parse_mapping(&[0, 130, 0, 2, 0, 0, 0, 42], OPCODE_MAP_TCP) {
+            Err(NatPmpError::NotAuthorized) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        match parse_mapping(&response, OPCODE_MAP_TCP) {
+            Err(NatPmpError::InvalidResponse) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,518 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rand::{self, Rng};
+
+use crate::errors::PcpError;
+use crate::firewall::protocol_number;
+use crate::natpmp::{connect, transaction};
+use crate::runtime::AsyncUdpSocket;
+use crate::PortMappingProtocol;
+
+const VERSION: u8 = 2;
+const SERVER_PORT: u16 = 5351;
+
+const OPCODE_ANNOUNCE: u8 = 0;
+const OPCODE_MAP: u8 = 1;
+const OPCODE_PEER: u8 = 2;
+// Set in the opcode of a response.
+const RESPONSE_BIT: u8 = 0x80;
+
+const OPTION_THIRD_PARTY: u8 = 1;
+const OPTION_PREFER_FAILURE: u8 = 2;
+
+const HEADER_LENGTH: usize = 24;
+const MAP_LENGTH: usize = 36;
+const PEER_LENGTH: usize = 56;
+
+// A request is sent again after about 3s, then after twice as long every time up to 1024s. The
+// RFC lets clients try forever, give up after about 45s.
+const INITIAL_TIMEOUT: Duration = Duration::from_secs(3);
+const MAX_TIMEOUT: Duration = Duration::from_secs(1024);
+const MAX_ATTEMPTS: u32 = 4;
+
+/// A gateway speaking the Port Control Protocol, as specified by RFC 6887.
+///
+/// PCP has no discovery, the gateway is the default router of the host or a carrier-grade NAT
+/// configured by the network. The gateway tracks the epoch of the server across requests to
+/// detect when it loses its mappings, clones share that tracking.
+#[derive(Clone, Debug)]
+pub struct PcpGateway {
+    addr: SocketAddr,
+    // The last epoch of the server and when it was received.
+    epoch: Arc<Mutex<Option<(u32, Instant)>>>,
+    // The external address of the last mapping granted by the server.
+    external_ip: Arc<Mutex<Option<IpAddr>>>,
+}
+
+/// Options of the requests of a `PcpGateway`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PcpOptions {
+    /// Fail with `PcpError::PortInUse` instead of mapping another external port than the one
+    /// asked for. Only used by `PcpGateway::add_port`.
+    pub prefer_failure: bool,
+    /// Create the mapping for another host. The gateway must allow it.
+    pub third_party: Option<IpAddr>,
+}
+
+/// A mapping created by `PcpGateway::add_port` or `PcpGateway::add_peer`.
+///
+/// Keep it to renew or remove the mapping, the gateway identifies mappings by a nonce it holds.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PcpMapping {
+    /// The protocol of the mapping.
+    pub protocol: PortMappingProtocol,
+    /// The address the traffic is sent to.
+    pub local_addr: SocketAddr,
+    /// The external address and port assigned by the gateway.
+    pub external_addr: SocketAddr,
+    /// The remote peer of a PEER mapping.
+    pub remote_addr: Option<SocketAddr>,
+    /// The lease granted by the gateway in seconds, which may differ from the one asked for.
+    pub lease_duration: u32,
+    /// The seconds since the gateway started.
+    pub epoch: u32,
+    /// Whether the epoch shows that the gateway lost its mappings since the previous response.
+    /// Every other mapping must be created again when it did.
+    pub state_lost: bool,
+    nonce: [u8; 12],
+    third_party: Option<IpAddr>,
+}
+
+impl PcpGateway {
+    /// Create a gateway for the PCP server with the given address, which can be an IPv4 or an IPv6 address.
+    pub fn new(ip: IpAddr) -> PcpGateway {
+        PcpGateway {
+            addr: SocketAddr::new(ip, SERVER_PORT),
+            epoch: Arc::new(Mutex::new(None)),
+            external_ip: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// get the address the gateway receives requests on
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Send an ANNOUNCE request, which checks that the gateway speaks PCP without changing anything on it.
+    ///
+    /// # Returns
+    ///
+    /// The epoch of the gateway, the seconds since it started, on success. Otherwise an error.
+    pub async fn announce(&self) -> Result<u32, PcpError> {
+        let socket = connect(unspecified(&self.addr.ip()), self.addr).await?;
+        let datagram = build_announce(socket.local_addr()?.ip());
+        let response = transaction(&socket, &datagram, &retransmission_timeouts(), is_response).await?;
+        let server_epoch = parse_announce(&response)?;
+        observe_epoch(&self.epoch, server_epoch);
+        Ok(server_epoch)
+    }
+
+    /// Add a port mapping with the MAP opcode.
+    ///
+    /// The local_addr is the address where the traffic is sent to, an unspecified ip stands for
+    /// the address the gateway is reached from. It must be of the family of the gateway.
+    /// The external_port is only a suggestion unless `options.prefer_failure` is set, 0 lets the
+    /// gateway pick any port.
+    /// The lease_duration parameter is in seconds.
+    ///
+    /// # Returns
+    ///
+    /// The mapping granted by the gateway on success. Otherwise an error.
+    pub async fn add_port(&self, protocol: PortMappingProtocol, external_port: u16, local_addr: SocketAddr, lease_duration: u32, options: &PcpOptions) -> Result<PcpMapping, PcpError> {
+        if local_addr.port() == 0 {
+            return Err(PcpError::InternalPortZeroInvalid);
+        }
+        self.request(Request {
+            protocol,
+            local_addr,
+            external_addr: SocketAddr::new(unspecified(&self.addr.ip()), external_port),
+            remote_addr: None,
+            lease_duration,
+            options: options.clone(),
+            nonce: new_nonce(),
+        })
+        .await
+    }
+
+    /// Add a mapping for the traffic with a single remote peer with the PEER opcode.
+    ///
+    /// This creates or extends the mapping the gateway made for an outbound connection, so that
+    /// it outlives a period without traffic. The local_addr and lease_duration are as with `add_port`.
+    pub async fn add_peer(&self, protocol: PortMappingProtocol, local_addr: SocketAddr, remote_addr: SocketAddr, lease_duration: u32, options: &PcpOptions) -> Result<PcpMapping, PcpError> {
+        if local_addr.port() == 0 {
+            return Err(PcpError::InternalPortZeroInvalid);
+        }
+        self.request(Request {
+            protocol,
+            local_addr,
+            external_addr: SocketAddr::new(unspecified(&self.addr.ip()), 0),
+            remote_addr: Some(remote_addr),
+            lease_duration,
+            options: PcpOptions {
+                prefer_failure: false,
+                third_party: options.third_party,
+            },
+            nonce: new_nonce(),
+        })
+        .await
+    }
+
+    /// Renew a mapping, asking for the external address it already has.
+    pub async fn renew(&self, mapping: &PcpMapping, lease_duration: u32) -> Result<PcpMapping, PcpError> {
+        self.request(Request {
+            protocol: mapping.protocol,
+            local_addr: mapping.local_addr,
+            external_addr: mapping.external_addr,
+            remote_addr: mapping.remote_addr,
+            lease_duration,
+            options: PcpOptions {
+                prefer_failure: false,
+                third_party: mapping.third_party,
+            },
+            nonce: mapping.nonce,
+        })
+        .await
+    }
+
+    /// Remove a mapping.
+    pub async fn remove(&self, mapping: &PcpMapping) -> Result<(), PcpError> {
+        self.renew(mapping, 0).await.map(|_| ())
+    }
+
+    async fn request(&self, request: Request) -> Result<PcpMapping, PcpError> {
+        // The local address of a mapping made for a third party is not an address of this host.
+        let bind_ip = match request.options.third_party {
+            Some(_) => unspecified(&self.addr.ip()),
+            None => request.local_addr.ip(),
+        };
+        let socket = connect(bind_ip, self.addr).await?;
+        // The gateway checks that the request comes from the client address it carries.
+        let client_ip = socket.local_addr()?.ip();
+        let internal_addr = request.internal_addr(client_ip);
+        let datagram = build_request(&request, client_ip);
+
+        let response = transaction(&socket, &datagram, &retransmission_timeouts(), is_response).await?;
+        let (lease_duration, server_epoch, external_addr) = parse_response(&response, request.protocol)?;
+        if lease_duration != 0 {
+            *self.external_ip.lock().unwrap() = Some(external_addr.ip());
+        }
+        Ok(PcpMapping {
+            protocol: request.protocol,
+            local_addr: internal_addr,
+            external_addr,
+            remote_addr: request.remote_addr,
+            lease_duration,
+            epoch: server_epoch,
+            state_lost: observe_epoch(&self.epoch, server_epoch),
+            nonce: request.nonce,
+            third_party: request.options.third_party,
+        })
+    }
+
+    // The external address of the last mapping granted by the server, if any.
+    pub(crate) fn last_external_ip(&self) -> Option<IpAddr> {
+        *self.external_ip.lock().unwrap()
+    }
+}
+
+// The fields of a MAP or PEER request.
+struct Request {
+    protocol: PortMappingProtocol,
+    local_addr: SocketAddr,
+    external_addr: SocketAddr,
+    remote_addr: Option<SocketAddr>,
+    lease_duration: u32,
+    options: PcpOptions,
+    nonce: [u8; 12],
+}
+
+impl Request {
+    // The address of the mapping, on this host unless made for a third party.
+    fn internal_addr(&self, client_ip: IpAddr) -> SocketAddr {
+        SocketAddr::new(self.options.third_party.unwrap_or(client_ip), self.local_addr.port())
+    }
+}
+
+fn new_nonce() -> [u8; 12] {
+    let mut nonce = [0; 12];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    nonce
+}
+
+fn unspecified(ip: &IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        IpAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    }
+}
+
+// PCP carries every address as an IPv6 address, IPv4 ones are mapped.
+fn address_bytes(ip: &IpAddr) -> [u8; 16] {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped().octets(),
+        IpAddr::V6(ip) => ip.octets(),
+    }
+}
+
+fn parse_address(bytes: &[u8]) -> IpAddr {
+    let mut octets = [0; 16];
+    octets.copy_from_slice(&bytes[..16]);
+    let ip = Ipv6Addr::from(octets);
+    match ip.to_ipv4() {
+        Some(ipv4) if ip.segments()[..6] == [0, 0, 0, 0, 0, 0xffff] => IpAddr::V4(ipv4),
+        _ => IpAddr::V6(ip),
+    }
+}
+
+fn build_request(request: &Request, client_ip: IpAddr) -> Vec<u8> {
+    let opcode = if request.remote_addr.is_some() { OPCODE_PEER } else { OPCODE_MAP };
+    let mut datagram = vec![VERSION, opcode, 0, 0];
+    datagram.extend_from_slice(&request.lease_duration.to_be_bytes());
+    datagram.extend_from_slice(&address_bytes(&client_ip));
+
+    datagram.extend_from_slice(&request.nonce);
+    datagram.extend_from_slice(&[protocol_number(request.protocol) as u8, 0, 0, 0]);
+    datagram.extend_from_slice(&request.local_addr.port().to_be_bytes());
+    datagram.extend_from_slice(&request.external_addr.port().to_be_bytes());
+    datagram.extend_from_slice(&address_bytes(&request.external_addr.ip()));
+    if let Some(remote_addr) = request.remote_addr {
+        datagram.extend_from_slice(&remote_addr.port().to_be_bytes());
+        datagram.extend_from_slice(&[0, 0]);
+        datagram.extend_from_slice(&address_bytes(&remote_addr.ip()));
+    }
+
+    if let Some(third_party) = request.options.third_party {
+        datagram.extend_from_slice(&[OPTION_THIRD_PARTY, 0, 0, 16]);
+        datagram.extend_from_slice(&address_bytes(&third_party));
+    }
+    if request.options.prefer_failure && request.remote_addr.is_none() {
+        datagram.extend_from_slice(&[OPTION_PREFER_FAILURE, 0, 0, 0]);
+    }
+    datagram
+}
+
+// An ANNOUNCE request is a bare header.
+fn build_announce(client_ip: IpAddr) -> Vec<u8> {
+    let mut datagram = vec![VERSION, OPCODE_ANNOUNCE, 0, 0, 0, 0, 0, 0];
+    datagram.extend_from_slice(&address_bytes(&client_ip));
+    datagram
+}
+
+// A response answers the request if it has the same opcode and nonce.
+//
+// Servers that only speak NAT-PMP answer with a version 0 UNSUPP_VERSION response of their own
+// protocol, whose result code is where PCP has it.
+fn is_response(request: &[u8], response: &[u8]) -> bool {
+    if response.len() >= 4 && response[0] == 0 {
+        return response[1] == request[1] | RESPONSE_BIT && response[2..4] == [0, 1];
+    }
+    let nonce = HEADER_LENGTH..HEADER_LENGTH + 12;
+    response.len() >= HEADER_LENGTH
+        && response[0] == VERSION
+        && response[1] == request[1] | RESPONSE_BIT
+        // Error responses may come without the payload of the request, and ANNOUNCE has none.
+        && (response.len() < nonce.end || request.len() < nonce.end || response[nonce.clone()] == request[nonce])
+}
+
+// The error a result code stands for, if any.
+fn check_result_code(code: u8) -> Result<(), PcpError> {
+    match code {
+        0 => Ok(()),
+        1 => Err(PcpError::UnsupportedVersion),
+        2 => Err(PcpError::ActionNotAuthorized),
+        3 | 6 => Err(PcpError::MalformedRequest),
+        4 => Err(PcpError::UnsupportedOpcode),
+        5 => Err(PcpError::UnsupportedOption),
+        7 => Err(PcpError::NetworkFailure),
+        8 | 10 => Err(PcpError::NoPortsAvailable),
+        9 => Err(PcpError::UnsupportedProtocol),
+        11 => Err(PcpError::PortInUse),
+        12 => Err(PcpError::AddressMismatch),
+        13 => Err(PcpError::ExcessiveRemotePeers),
+        code => Err(PcpError::UnknownResultCode(code)),
+    }
+}
+
+// Parse the epoch of an ANNOUNCE response.
+fn parse_announce(response: &[u8]) -> Result<u32, PcpError> {
+    check_result_code(response[3])?;
+    Ok(u32::from_be_bytes([response[8], response[9], response[10], response[11]]))
+}
+
+// Parse the lease, the epoch and the external address of a response.
+fn parse_response(response: &[u8], protocol: PortMappingProtocol) -> Result<(u32, u32, SocketAddr), PcpError> {
+    check_result_code(response[3])?;
+    let length = if response[1] & !RESPONSE_BIT == OPCODE_PEER { PEER_LENGTH } else { MAP_LENGTH };
+    if response.len() < HEADER_LENGTH + length || u16::from(response[HEADER_LENGTH + 12]) != protocol_number(protocol) {
+        return Err(PcpError::InvalidResponse);
+    }
+    let lease_duration = u32::from_be_bytes([response[4], response[5], response[6], response[7]]);
+    let epoch = u32::from_be_bytes([response[8], response[9], response[10], response[11]]);
+    let payload = &response[HEADER_LENGTH..];
+    let external_port = u16::from_be_bytes([payload[18], payload[19]]);
+    let external_ip = parse_address(&payload[20..36]);
+    Ok((lease_duration, epoch, SocketAddr::new(external_ip, external_port)))
+}
+
+// Record the epoch of a response and tell whether the server lost its state since the previous one.
+fn observe_epoch(epoch: &Mutex<Option<(u32, Instant)>>, server_epoch: u32) -> bool {
+    let now = Instant::now();
+    let mut epoch = epoch.lock().unwrap();
+    let lost = match *epoch {
+        Some((previous, then)) => epoch_lost(previous, now - then, server_epoch),
+        None => false,
+    };
+    *epoch = Some((server_epoch, now));
+    lost
+}
+
+// The epoch validation of the RFC: the epoch of the server must not go back, and must move at
+// about the same pace as the clock of the client.
+fn epoch_lost(previous: u32, elapsed: Duration, current: u32) -> bool {
+    if u64::from(current) + 1 < u64::from(previous) {
+        return true;
+    }
+    let client_delta = elapsed.as_secs();
+    let server_delta = u64::from(current.saturating_sub(previous));
+    client_delta + 2 < server_delta - server_delta / 16 || server_delta + 2 < client_delta - client_delta / 16
+}
+
+// The timeouts of the attempts, doubling with a tenth of jitter so that clients do not retry in step.
+fn retransmission_timeouts() -> Vec<Duration> {
+    let mut rng = rand::thread_rng();
+    (0..MAX_ATTEMPTS)
+        .map(|attempt| {
+            let timeout = (INITIAL_TIMEOUT * 2u32.pow(attempt)).min(MAX_TIMEOUT);
+            let millis = timeout.as_secs() * 1000;
+            Duration::from_millis(millis - millis / 10 + rng.gen_range(0, millis / 5))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_map_request() {
+        let nonce = [7; 12];
+        let request = Request {
+            protocol: PortMappingProtocol::UDP,
+            local_addr: "0.0.0.0:5000".parse().unwrap(),
+            external_addr: "0.0.0.0:6000".parse().unwrap(),
+            remote_addr: None,
+            lease_duration: 3600,
+            options: PcpOptions {
+                prefer_failure: true,
+                third_party: Some("192.168.1.20".parse().unwrap()),
+            },
+            nonce,
+        };
+        assert_eq!(request.internal_addr("192.168.1.10".parse().unwrap()), "192.168.1.20:5000".parse().unwrap());
+        let request = build_request(&request, "192.168.1.10".parse().unwrap());
+        assert_eq!(request.len(), HEADER_LENGTH + MAP_LENGTH + 20 + 4);
+        assert_eq!(&request[..8], &[2, 1, 0, 0, 0, 0, 0x0e, 0x10]);
+        assert_eq!(&request[8..24], &address_bytes(&"192.168.1.10".parse().unwrap()));
+        assert_eq!(&request[24..36], &nonce);
+        assert_eq!(&request[36..44], &[17, 0, 0, 0, 0x13, 0x88, 0x17, 0x70]);
+        assert_eq!(&request[60..64], &[OPTION_THIRD_PARTY, 0, 0, 16]);
+        assert_eq!(&request[80..], &[OPTION_PREFER_FAILURE, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let nonce = [7; 12];
+        let request = Request {
+            protocol: PortMappingProtocol::TCP,
+            local_addr: "[2001:db8::10]:5000".parse().unwrap(),
+            external_addr: "[::]:0".parse().unwrap(),
+            remote_addr: None,
+            lease_duration: 3600,
+            options: PcpOptions::default(),
+            nonce,
+        };
+        let request = build_request(&request, "2001:db8::10".parse().unwrap());
+        let mut response = request.clone();
+        response[1] |= RESPONSE_BIT;
+        response[4..8].copy_from_slice(&1800u32.to_be_bytes());
+        response[8..12].copy_from_slice(&42u32.to_be_bytes());
+        response[42..44].copy_from_slice(&6000u16.to_be_bytes());
+        response[44..60].copy_from_slice(&address_bytes(&"203.0.113.7".parse().unwrap()));
+        assert!(is_response(&request, &response));
+        assert_eq!(
+            parse_response(&response, PortMappingProtocol::TCP).unwrap(),
+            (1800, 42, "203.0.113.7:6000".parse().unwrap())
+        );
+
+        response[3] = 11;
+        match parse_response(&response, PortMappingProtocol::TCP) {
+            Err(PcpError::PortInUse) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        response[24] = 0;
+        assert!(!is_response(&request, &response));
+    }
+
+    #[test]
+    fn test_natpmp_unsupported_version() {
+        let request = Request {
+            protocol: PortMappingProtocol::UDP,
+            local_addr: "192.168.1.10:5000".parse().unwrap(),
+            external_addr: "0.0.0.0:0".parse().unwrap(),
+            remote_addr: None,
+            lease_duration: 3600,
+            options: PcpOptions::default(),
+            nonce: [7; 12],
+        };
+        let request = build_request(&request, "192.168.1.10".parse().unwrap());
+        // Version 0, the opcode of the request with the response bit, UNSUPP_VERSION and the epoch.
+        let mut response = [0, OPCODE_MAP | RESPONSE_BIT, 0, 1, 0, 0, 0x12, 0x34];
+        assert!(is_response(&request, &response));
+        match parse_response(&response, PortMappingProtocol::UDP) {
+            Err(PcpError::UnsupportedVersion) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        response[1] = OPCODE_PEER | RESPONSE_BIT;
+        assert!(!is_response(&request, &response));
+        response[1] = OPCODE_MAP | RESPONSE_BIT;
+        response[3] = 0;
+        assert!(!is_response(&request, &response));
+    }
+
+    #[test]
+    fn test_announce() {
+        let request = build_announce("192.168.1.10".parse().unwrap());
+        assert_eq!(request.len(), HEADER_LENGTH);
+        assert_eq!(&request[..8], &[2, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(&request[8..], &address_bytes(&"192.168.1.10".parse().unwrap()));
+
+        let mut response = request.clone();
+        response[1] |= RESPONSE_BIT;
+        response[8..12].copy_from_slice(&42u32.to_be_bytes());
+        assert!(is_response(&request, &response));
+        assert_eq!(parse_announce(&response).unwrap(), 42);
+        // A response with a payload answers a request without one.
+        response.extend_from_slice(&[0; MAP_LENGTH]);
+        assert!(is_response(&request, &response));
+
+        response[3] = 4;
+        match parse_announce(&response) {
+            Err(PcpError::UnsupportedOpcode) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        response[1] = OPCODE_MAP | RESPONSE_BIT;
+        assert!(!is_response(&request, &response));
+    }
+
+    #[test]
+    fn test_epoch_lost() {
+        let minute = Duration::from_secs(60);
+        assert!(!epoch_lost(1000, minute, 1060));
+        assert!(!epoch_lost(1000, minute, 1058));
+        assert!(epoch_lost(1000, minute, 10));
+        assert!(epoch_lost(1000, minute, 1200));
+        assert!(epoch_lost(1000, minute, 1001));
+    }
+}
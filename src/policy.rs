@@ -0,0 +1,71 @@
+use std::time::Duration;
+
+/// How the requests to a gateway are sent: timeouts, retries and the search for a free port.
+///
+/// Every `Gateway` has one, `RequestPolicy::default()` unless set with `Gateway::with_request_policy`.
+/// To use another policy for a single call, make the call on a copy of the gateway:
+/// `gateway.clone().with_request_policy(policy).add_port(..)`.
+///
+/// The policy is also put in the extensions of the requests given to the `Transport`, so that
+/// transports of your own can follow its timeouts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestPolicy {
+    /// How long to wait for the connection to the gateway, None to wait as long as the system does.
+    pub connect_timeout: Option<Duration>,
+    /// How long to wait for the response once connected, None to wait forever.
+    pub read_timeout: Option<Duration>,
+    /// How many times a request that failed with a transient HTTP or IO error is sent again.
+    ///
+    /// Requests that could not be sent, like when the connection was refused or timed out, are
+    /// always retried. Once a request may have reached the gateway, like after a lost connection or
+    /// a read timeout, only the actions reading the state of the gateway are, so that a mapping is
+    /// not added or deleted twice. Errors returned by the gateway are not retried.
+    pub retries: u32,
+    /// The delay before the first retry, doubled for every further one.
+    pub initial_backoff: Duration,
+    /// The longest delay before a retry.
+    pub max_backoff: Duration,
+    /// How many random external ports `Gateway::add_any_port` tries on gateways without the
+    /// AddAnyPortMapping action, before giving up with `AddAnyPortError::NoPortsAvailable`.
+    pub random_port_attempts: u32,
+}
+
+impl RequestPolicy {
+    // The delay before the given retry, counting from 0.
+    pub(crate) fn backoff(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry);
+        self.initial_backoff.checked_mul(factor).map_or(self.max_backoff, |delay| delay.min(self.max_backoff))
+    }
+}
+
+impl Default for RequestPolicy {
+    /// Connect within 5 seconds, get the response within 10 more, retry twice after 250ms then
+    /// 500ms, and try 20 random ports.
+    fn default() -> RequestPolicy {
+        RequestPolicy {
+            connect_timeout: Some(Duration::from_secs(5)),
+            read_timeout: Some(Duration::from_secs(10)),
+            retries: 2,
+            initial_backoff: Duration::from_millis(250),
+            max_backoff: Duration::from_secs(5),
+            random_port_attempts: 20,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let policy = RequestPolicy {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(1000),
+            ..RequestPolicy::default()
+        };
+        let delays = (0..6).map(|retry| policy.backoff(retry).as_millis()).collect::<Vec<_>>();
+        assert_eq!(delays, vec![100, 200, 400, 800, 1000, 1000]);
+        assert_eq!(policy.backoff(40), Duration::from_millis(1000));
+    }
+}
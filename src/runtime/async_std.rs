@@ -0,0 +1,92 @@
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use async_std::{net, task};
+use smol_hyper::rt::FuturesIo;
+
+use super::{AsyncTcpListener, AsyncUdpSocket, Runtime};
+
+pub(crate) struct AsyncStd;
+
+impl Runtime for AsyncStd {
+    type UdpSocket = UdpSocket;
+    type TcpListener = TcpListener;
+    type TcpStream = FuturesIo<net::TcpStream>;
+
+    fn spawn<F: Future<Output = ()> + Send + 'static>(future: F) {
+        task::spawn(future);
+    }
+
+    fn sleep(duration: Duration) -> impl Future<Output = ()> + Send + 'static {
+        task::sleep(duration)
+    }
+
+    async fn connect(addr: String) -> io::Result<Self::TcpStream> {
+        net::TcpStream::connect(addr).await.map(FuturesIo::new)
+    }
+
+    fn shutdown_signal() -> impl Future<Output = io::Result<()>> + Send {
+        super::wait_for_signal()
+    }
+
+    #[cfg(any(test, feature = "sync"))]
+    fn block_on<F: Future>(future: F) -> F::Output {
+        task::block_on(future)
+    }
+}
+
+pub(crate) struct UdpSocket(net::UdpSocket);
+
+impl AsyncUdpSocket for UdpSocket {
+    async fn bind(addr: SocketAddr) -> io::Result<Self> {
+        net::UdpSocket::bind(addr).await.map(UdpSocket)
+    }
+
+    fn from_std(socket: std::net::UdpSocket) -> io::Result<Self> {
+        Ok(UdpSocket(net::UdpSocket::from(socket)))
+    }
+
+    async fn connect(&self, addr: SocketAddr) -> io::Result<()> {
+        self.0.connect(addr).await
+    }
+
+    async fn send<'a>(&'a self, buf: &'a [u8]) -> io::Result<usize> {
+        self.0.send(buf).await
+    }
+
+    async fn recv<'a>(&'a self, buf: &'a mut [u8]) -> io::Result<usize> {
+        self.0.recv(buf).await
+    }
+
+    async fn send_to<'a>(&'a self, buf: &'a [u8], addr: SocketAddr) -> io::Result<usize> {
+        self.0.send_to(buf, addr).await
+    }
+
+    async fn recv_from<'a>(&'a self, buf: &'a mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        self.0.recv_from(buf).await
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.0.local_addr()
+    }
+}
+
+pub(crate) struct TcpListener(net::TcpListener);
+
+impl AsyncTcpListener for TcpListener {
+    type Stream = FuturesIo<net::TcpStream>;
+
+    async fn bind(addr: SocketAddr) -> io::Result<Self> {
+        net::TcpListener::bind(addr).await.map(TcpListener)
+    }
+
+    async fn accept(&self) -> io::Result<Self::Stream> {
+        self.0.accept().await.map(|(stream, _)| FuturesIo::new(stream))
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.0.local_addr()
+    }
+}
@@ -0,0 +1,169 @@
+// The sockets, timers and tasks of the async runtime the crate runs on.
+//
+// The runtime is picked at compile time by the `tokio`, `async-std` and `smol` features. When
+// several are enabled, tokio is preferred over async-std, and async-std over smol.
+
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::pin;
+use std::time::{Duration, Instant};
+
+use futures::future::{self, Either};
+
+#[cfg(all(feature = "async-std", not(feature = "tokio")))]
+mod async_std;
+#[cfg(not(any(feature = "tokio", feature = "async-std", feature = "smol")))]
+mod none;
+#[cfg(all(feature = "smol", not(any(feature = "tokio", feature = "async-std"))))]
+mod smol;
+#[cfg(feature = "tokio")]
+mod tokio;
+
+#[cfg(all(feature = "async-std", not(feature = "tokio")))]
+pub(crate) type Rt = self::async_std::AsyncStd;
+#[cfg(all(feature = "smol", not(any(feature = "tokio", feature = "async-std"))))]
+pub(crate) type Rt = self::smol::Smol;
+#[cfg(feature = "tokio")]
+pub(crate) type Rt = self::tokio::Tokio;
+
+#[cfg(not(any(feature = "tokio", feature = "async-std", feature = "smol")))]
+pub(crate) type Rt = self::none::NoRuntime;
+
+#[cfg(not(any(feature = "tokio", feature = "async-std", feature = "smol")))]
+compile_error!("one of the `tokio`, `async-std` and `smol` features must be enabled");
+
+pub(crate) type UdpSocket = <Rt as Runtime>::UdpSocket;
+pub(crate) type TcpListener = <Rt as Runtime>::TcpListener;
+pub(crate) type TcpStream = <Rt as Runtime>::TcpStream;
+
+// What the crate needs from a runtime.
+pub(crate) trait Runtime {
+    type UdpSocket: AsyncUdpSocket;
+    type TcpListener: AsyncTcpListener<Stream = Self::TcpStream>;
+    // A TCP connection, wrapped for hyper.
+    type TcpStream: hyper::rt::Read + hyper::rt::Write + Send + Unpin + 'static;
+
+    // Run the future on its own. Dropped if there is no runtime to run it, as when a handle is
+    // dropped after the runtime stopped.
+    fn spawn<F: Future<Output = ()> + Send + 'static>(future: F);
+
+    fn sleep(duration: Duration) -> impl Future<Output = ()> + Send + 'static;
+
+    fn connect(addr: String) -> impl Future<Output = io::Result<Self::TcpStream>> + Send;
+
+    // Wait for SIGINT or SIGTERM, or Ctrl-C on platforms other than Unix.
+    fn shutdown_signal() -> impl Future<Output = io::Result<()>> + Send;
+
+    // Run the future to completion on the current thread.
+    #[cfg(any(test, feature = "sync"))]
+    fn block_on<F: Future>(future: F) -> F::Output;
+}
+
+pub(crate) trait AsyncUdpSocket: Sized + Send + Sync + 'static {
+    fn bind(addr: SocketAddr) -> impl Future<Output = io::Result<Self>> + Send;
+
+    // The socket must be non-blocking.
+    fn from_std(socket: std::net::UdpSocket) -> io::Result<Self>;
+
+    fn connect(&self, addr: SocketAddr) -> impl Future<Output = io::Result<()>> + Send;
+
+    fn send<'a>(&'a self, buf: &'a [u8]) -> impl Future<Output = io::Result<usize>> + Send + 'a;
+
+    fn recv<'a>(&'a self, buf: &'a mut [u8]) -> impl Future<Output = io::Result<usize>> + Send + 'a;
+
+    fn send_to<'a>(&'a self, buf: &'a [u8], addr: SocketAddr) -> impl Future<Output = io::Result<usize>> + Send + 'a;
+
+    fn recv_from<'a>(&'a self, buf: &'a mut [u8]) -> impl Future<Output = io::Result<(usize, SocketAddr)>> + Send + 'a;
+
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+}
+
+pub(crate) trait AsyncTcpListener: Sized + Send + Sync + 'static {
+    type Stream;
+
+    fn bind(addr: SocketAddr) -> impl Future<Output = io::Result<Self>> + Send;
+
+    fn accept(&self) -> impl Future<Output = io::Result<Self::Stream>> + Send + '_;
+
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+}
+
+pub(crate) fn spawn<F: Future<Output = ()> + Send + 'static>(future: F) {
+    Rt::spawn(future)
+}
+
+pub(crate) fn sleep(duration: Duration) -> impl Future<Output = ()> + Send + 'static {
+    Rt::sleep(duration)
+}
+
+pub(crate) fn sleep_until(deadline: Instant) -> impl Future<Output = ()> + Send + 'static {
+    Rt::sleep(deadline.saturating_duration_since(Instant::now()))
+}
+
+pub(crate) fn connect(addr: String) -> impl Future<Output = io::Result<TcpStream>> + Send {
+    Rt::connect(addr)
+}
+
+pub(crate) fn shutdown_signal() -> impl Future<Output = io::Result<()>> + Send {
+    Rt::shutdown_signal()
+}
+
+#[cfg(any(test, feature = "sync"))]
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    Rt::block_on(future)
+}
+
+// The error of a future that did not complete in time.
+#[derive(Debug)]
+pub(crate) struct Elapsed;
+
+pub(crate) async fn timeout<F: Future>(duration: Duration, future: F) -> Result<F::Output, Elapsed> {
+    match future::select(pin!(future), pin!(sleep(duration))).await {
+        Either::Left((output, _)) => Ok(output),
+        Either::Right(_) => Err(Elapsed),
+    }
+}
+
+pub(crate) async fn timeout_at<F: Future>(deadline: Instant, future: F) -> Result<F::Output, Elapsed> {
+    match future::select(pin!(future), pin!(sleep_until(deadline))).await {
+        Either::Left((output, _)) => Ok(output),
+        Either::Right(_) => Err(Elapsed),
+    }
+}
+
+// Ticks at a fixed rate, the first one a period from now. Missed ticks are caught up at once.
+pub(crate) struct Interval {
+    next: Instant,
+    period: Duration,
+}
+
+pub(crate) fn interval(period: Duration) -> Interval {
+    Interval {
+        next: Instant::now() + period,
+        period,
+    }
+}
+
+impl Interval {
+    pub(crate) async fn tick(&mut self) {
+        sleep_until(self.next).await;
+        self.next += self.period;
+    }
+}
+
+// Shared by the runtimes without signal handling of their own.
+#[cfg(all(any(feature = "async-std", feature = "smol"), not(feature = "tokio")))]
+async fn wait_for_signal() -> io::Result<()> {
+    use async_signal::{Signal, Signals};
+    use futures::StreamExt;
+
+    #[cfg(unix)]
+    let mut signals = Signals::new([Signal::Int, Signal::Term])?;
+    #[cfg(not(unix))]
+    let mut signals = Signals::new([Signal::Int])?;
+    match signals.next().await {
+        Some(signal) => signal.map(|_| ()),
+        None => Err(io::Error::new(io::ErrorKind::BrokenPipe, "the signal handler stopped")),
+    }
+}
@@ -0,0 +1,117 @@
+// Stands in for a runtime when no runtime feature is enabled, so that the `compile_error!` is
+// the only error reported. None of it can run.
+
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::future;
+
+use super::{AsyncTcpListener, AsyncUdpSocket, Runtime};
+
+pub(crate) struct NoRuntime;
+
+// A socket or connection, which fails at everything.
+pub(crate) struct Unsupported;
+
+fn unsupported() -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, "no runtime feature is enabled")
+}
+
+impl Runtime for NoRuntime {
+    type UdpSocket = Unsupported;
+    type TcpListener = Unsupported;
+    type TcpStream = Unsupported;
+
+    fn spawn<F: Future<Output = ()> + Send + 'static>(_: F) {}
+
+    fn sleep(_: Duration) -> impl Future<Output = ()> + Send + 'static {
+        future::pending()
+    }
+
+    async fn connect(_: String) -> io::Result<Self::TcpStream> {
+        Err(unsupported())
+    }
+
+    async fn shutdown_signal() -> io::Result<()> {
+        Err(unsupported())
+    }
+
+    #[cfg(any(test, feature = "sync"))]
+    fn block_on<F: Future>(_: F) -> F::Output {
+        panic!("no runtime feature is enabled")
+    }
+}
+
+impl AsyncUdpSocket for Unsupported {
+    async fn bind(_: SocketAddr) -> io::Result<Self> {
+        Err(unsupported())
+    }
+
+    fn from_std(_: std::net::UdpSocket) -> io::Result<Self> {
+        Err(unsupported())
+    }
+
+    async fn connect(&self, _: SocketAddr) -> io::Result<()> {
+        Err(unsupported())
+    }
+
+    async fn send<'a>(&'a self, _: &'a [u8]) -> io::Result<usize> {
+        Err(unsupported())
+    }
+
+    async fn recv<'a>(&'a self, _: &'a mut [u8]) -> io::Result<usize> {
+        Err(unsupported())
+    }
+
+    async fn send_to<'a>(&'a self, _: &'a [u8], _: SocketAddr) -> io::Result<usize> {
+        Err(unsupported())
+    }
+
+    async fn recv_from<'a>(&'a self, _: &'a mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        Err(unsupported())
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Err(unsupported())
+    }
+}
+
+impl AsyncTcpListener for Unsupported {
+    type Stream = Unsupported;
+
+    async fn bind(_: SocketAddr) -> io::Result<Self> {
+        Err(unsupported())
+    }
+
+    async fn accept(&self) -> io::Result<Self::Stream> {
+        Err(unsupported())
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Err(unsupported())
+    }
+}
+
+impl hyper::rt::Read for Unsupported {
+    fn poll_read(self: Pin<&mut Self>, _: &mut Context<'_>, _: hyper::rt::ReadBufCursor<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Err(unsupported()))
+    }
+}
+
+impl hyper::rt::Write for Unsupported {
+    fn poll_write(self: Pin<&mut Self>, _: &mut Context<'_>, _: &[u8]) -> Poll<io::Result<usize>> {
+        Poll::Ready(Err(unsupported()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Err(unsupported()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Err(unsupported()))
+    }
+}
@@ -0,0 +1,111 @@
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use hyper_util::rt::TokioIo;
+use tokio::net;
+use tokio::runtime::Handle;
+use tokio::time;
+
+use super::{AsyncTcpListener, AsyncUdpSocket, Runtime};
+
+pub(crate) struct Tokio;
+
+impl Runtime for Tokio {
+    type UdpSocket = UdpSocket;
+    type TcpListener = TcpListener;
+    type TcpStream = TokioIo<net::TcpStream>;
+
+    fn spawn<F: Future<Output = ()> + Send + 'static>(future: F) {
+        if let Ok(handle) = Handle::try_current() {
+            handle.spawn(future);
+        }
+    }
+
+    fn sleep(duration: Duration) -> impl Future<Output = ()> + Send + 'static {
+        time::sleep(duration)
+    }
+
+    async fn connect(addr: String) -> io::Result<Self::TcpStream> {
+        net::TcpStream::connect(addr).await.map(TokioIo::new)
+    }
+
+    #[cfg(unix)]
+    async fn shutdown_signal() -> io::Result<()> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut sigint = signal(SignalKind::interrupt())?;
+        let mut sigterm = signal(SignalKind::terminate())?;
+        tokio::select! {
+            _ = sigint.recv() => (),
+            _ = sigterm.recv() => (),
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    async fn shutdown_signal() -> io::Result<()> {
+        tokio::signal::ctrl_c().await
+    }
+
+    #[cfg(any(test, feature = "sync"))]
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().expect("failed to create a runtime");
+        runtime.block_on(future)
+    }
+}
+
+pub(crate) struct UdpSocket(net::UdpSocket);
+
+impl AsyncUdpSocket for UdpSocket {
+    async fn bind(addr: SocketAddr) -> io::Result<Self> {
+        net::UdpSocket::bind(addr).await.map(UdpSocket)
+    }
+
+    fn from_std(socket: std::net::UdpSocket) -> io::Result<Self> {
+        net::UdpSocket::from_std(socket).map(UdpSocket)
+    }
+
+    async fn connect(&self, addr: SocketAddr) -> io::Result<()> {
+        self.0.connect(addr).await
+    }
+
+    async fn send<'a>(&'a self, buf: &'a [u8]) -> io::Result<usize> {
+        self.0.send(buf).await
+    }
+
+    async fn recv<'a>(&'a self, buf: &'a mut [u8]) -> io::Result<usize> {
+        self.0.recv(buf).await
+    }
+
+    async fn send_to<'a>(&'a self, buf: &'a [u8], addr: SocketAddr) -> io::Result<usize> {
+        self.0.send_to(buf, addr).await
+    }
+
+    async fn recv_from<'a>(&'a self, buf: &'a mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        self.0.recv_from(buf).await
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.0.local_addr()
+    }
+}
+
+pub(crate) struct TcpListener(net::TcpListener);
+
+impl AsyncTcpListener for TcpListener {
+    type Stream = TokioIo<net::TcpStream>;
+
+    async fn bind(addr: SocketAddr) -> io::Result<Self> {
+        net::TcpListener::bind(addr).await.map(TcpListener)
+    }
+
+    async fn accept(&self) -> io::Result<Self::Stream> {
+        self.0.accept().await.map(|(stream, _)| TokioIo::new(stream))
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.0.local_addr()
+    }
+}
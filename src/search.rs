@@ -1,104 +1,431 @@
+use std::collections::HashSet;
 use std::io;
-use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
 use std::str;
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use bytes::Bytes;
 use futures::future;
-use futures::{Future, IntoFuture, Stream};
-use hyper;
-use tokio::prelude::FutureExt;
-use tokio::net::UdpSocket;
+use futures::stream::{self, BoxStream};
+use futures::{Stream, StreamExt, TryStreamExt};
+use hyper::{Request, Uri};
 use xml::reader::XmlEvent;
 use xml::EventReader;
 use regex::Regex;
+use socket2::{Domain, Protocol, Socket, Type};
 
 use crate::errors::SearchError;
+use crate::runtime::{self, AsyncUdpSocket, UdpSocket};
+use crate::transport::{default_transport, Transport};
 use crate::Gateway;
 
-// Content of the request.
-pub const SEARCH_REQUEST: &'static str = "M-SEARCH * HTTP/1.1\r
-Host:239.255.255.250:1900\r
-ST:urn:schemas-upnp-org:device:InternetGatewayDevice:1\r
+// Multicast group of SSDP over IPv4.
+const SSDP_MULTICAST_V4: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
+// Link-local and site-local multicast groups of SSDP over IPv6.
+const SSDP_MULTICAST_V6: [Ipv6Addr; 2] = [
+    Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xc),
+    Ipv6Addr::new(0xff05, 0, 0, 0, 0, 0, 0, 0xc),
+];
+const SSDP_PORT: u16 = 1900;
+
+// Device types searched for, IGDv2 devices do not always answer a search for IGDv1.
+const SEARCH_TARGETS: [&str; 2] = [
+    "urn:schemas-upnp-org:device:InternetGatewayDevice:1",
+    "urn:schemas-upnp-org:device:InternetGatewayDevice:2",
+];
+
+// Content of the request for the given device type sent to the given multicast address.
+fn search_request(target: &SocketAddr, search_target: &str) -> String {
+    format!(
+        "M-SEARCH * HTTP/1.1\r
+Host:{}\r
+ST:{}\r
 Man:\"ssdp:discover\"\r
-MX:3\r\n\r\n";
+MX:3\r\n\r\n",
+        target, search_target
+    )
+}
+
+// Multicast addresses the search is sent to from a socket bound to the given address.
+fn search_targets(ip: &IpAddr) -> Vec<SocketAddr> {
+    match ip {
+        IpAddr::V4(_) => vec![SocketAddr::new(SSDP_MULTICAST_V4.into(), SSDP_PORT)],
+        IpAddr::V6(_) => SSDP_MULTICAST_V6.iter().map(|group| SocketAddr::new((*group).into(), SSDP_PORT)).collect(),
+    }
+}
 
 /// Search gateway, bind to all interfaces and use a timeout of 3 seconds.
 ///
 /// Bind to all interfaces.
 /// The request will timeout after 3 seconds.
-pub fn search_gateway() -> impl Future<Item = Gateway, Error = SearchError> {
-    search_gateway_timeout(Duration::from_secs(3))
+pub async fn search_gateway() -> Result<Gateway, SearchError> {
+    search_gateway_timeout(Duration::from_secs(3)).await
 }
 
 /// Search gateway, bind to all interfaces and use the given duration for the timeout.
 ///
 /// Bind to all interfaces.
 /// The request will timeout after the given duration.
-pub fn search_gateway_timeout(timeout: Duration) -> impl Future<Item = Gateway, Error = SearchError> {
-    search_gateway_from_timeout(Ipv4Addr::new(0, 0, 0, 0), timeout)
+pub async fn search_gateway_timeout(timeout: Duration) -> Result<Gateway, SearchError> {
+    search_gateway_from_timeout(Ipv4Addr::new(0, 0, 0, 0), timeout).await
 }
 
 /// Search gateway, bind to the given interface and use a time of 3 seconds.
 ///
-/// Bind to the given interface.
+/// Bind to the given interface, which can be an IPv4 or an IPv6 address.
 /// The request will timeout after 3 seconds.
-pub fn search_gateway_from(ip: Ipv4Addr) -> impl Future<Item = Gateway, Error = SearchError> {
-    search_gateway_from_timeout(ip, Duration::from_secs(3))
+pub async fn search_gateway_from(ip: impl Into<IpAddr>) -> Result<Gateway, SearchError> {
+    search_gateway_from_timeout(ip, Duration::from_secs(3)).await
 }
 
 /// Search gateway, bind to the given interface and use the given duration for the timeout.
 ///
-/// Bind to the given interface.
+/// Bind to the given interface, which can be an IPv4 or an IPv6 address.
 /// The request will timeout after the given duration.
-pub fn search_gateway_from_timeout(ip: Ipv4Addr, timeout: Duration) -> impl Future<Item = Gateway, Error = SearchError> {
-    let addr = SocketAddr::V4(SocketAddrV4::new(ip, 0));
-    UdpSocket::bind(&addr)
-        .into_future()
-        .and_then(|socket| socket.send_dgram(SEARCH_REQUEST.as_bytes(), &"239.255.255.250:1900".parse().unwrap()))
-        .and_then(|(socket, _)| socket.recv_dgram(vec![0u8; 1500]))
-        .map_err(|err| SearchError::from(err))
-        .and_then(|(_sock, buf, n, _addr)| {
-            str::from_utf8(&buf[..n])
-                .map_err(|err| SearchError::from(err))
-                .and_then(|text| parse_result(text).ok_or(SearchError::InvalidResponse))
+pub async fn search_gateway_from_timeout(ip: impl Into<IpAddr>, timeout: Duration) -> Result<Gateway, SearchError> {
+    search_gateway_with_transport(ip, timeout, default_transport()).await
+}
+
+/// Search gateway, bind to the given interface and use the given duration for the timeout.
+///
+/// The description of the gateway is read with the given transport, and the requests to the
+/// gateway found are sent with it.
+pub async fn search_gateway_with_transport(ip: impl Into<IpAddr>, timeout: Duration, transport: Arc<dyn Transport>) -> Result<Gateway, SearchError> {
+    search_gateway_with_options(SearchOptions {
+        bind_addr: ip.into(),
+        timeout,
+        transport,
+        ..SearchOptions::default()
+    })
+    .await
+}
+
+/// Search all gateways, bind to all interfaces and listen for 3 seconds.
+///
+/// Bind to all interfaces.
+/// The stream ends after 3 seconds.
+pub fn search_gateways() -> impl Stream<Item = Result<Gateway, SearchError>> + Send {
+    search_gateways_timeout(Duration::from_secs(3))
+}
+
+/// Search all gateways, bind to all interfaces and listen for the given duration.
+///
+/// Bind to all interfaces.
+/// The stream ends after the given duration.
+pub fn search_gateways_timeout(timeout: Duration) -> impl Stream<Item = Result<Gateway, SearchError>> + Send {
+    search_gateways_from_timeout(Ipv4Addr::new(0, 0, 0, 0), timeout)
+}
+
+/// Search all gateways, bind to the given interface and listen for 3 seconds.
+///
+/// Bind to the given interface, which can be an IPv4 or an IPv6 address.
+/// The stream ends after 3 seconds.
+pub fn search_gateways_from(ip: impl Into<IpAddr>) -> impl Stream<Item = Result<Gateway, SearchError>> + Send {
+    search_gateways_from_timeout(ip, Duration::from_secs(3))
+}
+
+/// Search all gateways, bind to the given interface and listen for the given duration.
+///
+/// Every device answering the search is yielded once, even if it answers several times.
+/// Responses that can not be parsed, and devices that turn out not to be gateways, are skipped.
+/// When bound to an IPv6 address, the search is sent to the link-local and site-local SSDP groups,
+/// through the interface the system picks. Use `search_gateways_with_options` to choose it.
+/// The stream ends after the given duration, which also bounds the reading of the descriptions of
+/// the gateways. Failing to send the search ends it at once, while errors receiving the responses
+/// are yielded and the search goes on.
+pub fn search_gateways_from_timeout(ip: impl Into<IpAddr>, timeout: Duration) -> impl Stream<Item = Result<Gateway, SearchError>> + Send {
+    search_gateways_with_transport(ip, timeout, default_transport())
+}
+
+/// Search all gateways, bind to the given interface and listen for the given duration.
+///
+/// The descriptions of the gateways are read with the given transport, and the requests to the
+/// gateways found are sent with it. Otherwise the same as `search_gateways_from_timeout`.
+pub fn search_gateways_with_transport(ip: impl Into<IpAddr>, timeout: Duration, transport: Arc<dyn Transport>) -> impl Stream<Item = Result<Gateway, SearchError>> + Send {
+    search_gateways_with_options(SearchOptions {
+        bind_addr: ip.into(),
+        timeout,
+        transport,
+        ..SearchOptions::default()
+    })
+}
+
+/// How to search for gateways, for the `search_gateway_with_options` functions.
+#[derive(Clone, Debug)]
+pub struct SearchOptions {
+    /// The address to bind to, an IPv4 or an IPv6 address. All the IPv4 interfaces by default.
+    pub bind_addr: IpAddr,
+    /// How long to listen for responses, 3 seconds by default.
+    pub timeout: Duration,
+    /// The index of the network interface to search on over IPv6, 0 to let the system pick one.
+    ///
+    /// The search is sent to the multicast groups through this interface, and it is the scope of
+    /// a link-local bind_addr. Set it on hosts with several links, the gateways on the links the
+    /// system does not pick are not found otherwise. Ignored over IPv4, where the bind_addr
+    /// chooses the interface.
+    pub ipv6_interface: u32,
+    /// The transport the descriptions of the gateways are read with, and the requests to the
+    /// gateways found are sent with. The shared `HyperTransport` by default.
+    pub transport: Arc<dyn Transport>,
+}
+
+impl Default for SearchOptions {
+    fn default() -> SearchOptions {
+        SearchOptions {
+            bind_addr: Ipv4Addr::UNSPECIFIED.into(),
+            timeout: Duration::from_secs(3),
+            ipv6_interface: 0,
+            transport: default_transport(),
+        }
+    }
+}
+
+/// Search gateway with the given options.
+///
+/// Returns the first gateway found, or an error of kind `TimedOut` if none answers in time.
+pub async fn search_gateway_with_options(options: SearchOptions) -> Result<Gateway, SearchError> {
+    let mut gateways = Box::pin(search_gateways_with_options(options));
+    match gateways.next().await {
+        Some(gateway) => gateway,
+        None => Err(SearchError::IoError(io::Error::new(io::ErrorKind::TimedOut, "search timed out"))),
+    }
+}
+
+/// Search all gateways with the given options.
+///
+/// The same as `search_gateways_from_timeout` otherwise.
+pub fn search_gateways_with_options(options: SearchOptions) -> impl Stream<Item = Result<Gateway, SearchError>> + Send {
+    let SearchOptions {
+        bind_addr: ip,
+        timeout,
+        ipv6_interface,
+        transport,
+    } = options;
+    let search = async move {
+        let deadline = Instant::now() + timeout;
+        let socket = UdpSocket::from_std(bind_search(ip, ipv6_interface)?)?;
+        for target in search_targets(&ip) {
+            for st in SEARCH_TARGETS.iter() {
+                socket.send_to(search_request(&target, st).as_bytes(), target).await?;
+            }
+        }
+        Ok::<_, SearchError>(gateways(responses(socket, Some(deadline)), deadline, transport))
+    };
+    stream::once(search).try_flatten()
+}
+
+// Bind the socket the search is sent from. Over IPv6, the given interface is the scope of the
+// address and the one the multicast search goes out through, unless it is 0.
+fn bind_search(ip: IpAddr, ipv6_interface: u32) -> io::Result<std::net::UdpSocket> {
+    let addr = match ip {
+        IpAddr::V4(ip) => SocketAddr::new(ip.into(), 0),
+        IpAddr::V6(ip) => SocketAddrV6::new(ip, 0, 0, ipv6_interface).into(),
+    };
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    if addr.is_ipv6() && ipv6_interface != 0 {
+        socket.set_multicast_if_v6(ipv6_interface)?;
+    }
+    socket.bind(&addr.into())?;
+    socket.set_nonblocking(true)?;
+    Ok(socket.into())
+}
+
+// The gateways answering the search, each read from its description before the deadline.
+fn gateways(
+    responses: impl Stream<Item = Result<(String, SocketAddr), SearchError>> + Send,
+    deadline: Instant,
+    transport: Arc<dyn Transport>,
+) -> impl Stream<Item = Result<Gateway, SearchError>> + Send {
+    let mut seen = HashSet::new();
+    responses
+        .try_filter_map(move |(text, source)| {
+            let location = if is_gateway_response(&text) { parse_result(&text) } else { None };
+            let location = location.map(|(addr, path)| (with_scope_of(addr, source), path));
+            let found = location.and_then(|location| {
+                let key = parse_usn(&text)
+                    .map(|usn| usn.split("::").next().unwrap_or("").to_owned())
+                    .unwrap_or_else(|| format!("{}{}", location.0, location.1));
+                if seen.insert(key) {
+                    Some((location, parse_boot_id(&text, "BOOTID.UPNP.ORG")))
+                } else {
+                    None
+                }
+            });
+            future::ready(Ok(found))
         })
-        .and_then(move |location| get_control_url(&location).and_then(move |control_url| Ok(Gateway::new(location.0, control_url))))
-        .timeout(timeout)
-        .from_err()
+        .map_ok(move |(location, boot_id)| {
+            let transport = transport.clone();
+            async move {
+                let services = runtime::timeout_at(deadline, get_services(&*transport, &location)).await;
+                let gateway = services.ok().and_then(|result| result.ok()).and_then(|services| gateway_from_services(location.0, &services));
+                Ok(gateway.map(|gateway| gateway.with_boot_id(boot_id).with_transport(transport)))
+            }
+        })
+        .try_buffer_unordered(8)
+        .try_filter_map(|gateway| future::ready(Ok(gateway)))
 }
 
-fn get_control_url(location: &(SocketAddrV4, String)) -> Box<dyn Future<Item = String, Error = SearchError>> {
-    let client = hyper::Client::new();
-    let uri = match format!("http://{}{}", location.0, location.1).parse() {
-        Ok(uri) => uri,
-        Err(err) => return Box::new(future::err(SearchError::from(err))),
+// Stream of the responses received on the search socket until the deadline, if any, with the
+// addresses they came from.
+fn responses(socket: UdpSocket, deadline: Option<Instant>) -> impl Stream<Item = Result<(String, SocketAddr), SearchError>> + Send {
+    stream::unfold(socket, move |socket| async move {
+        let mut buf = [0u8; 1500];
+        loop {
+            let received = match deadline {
+                Some(deadline) => runtime::timeout_at(deadline, socket.recv_from(&mut buf)).await.ok()?,
+                None => socket.recv_from(&mut buf).await,
+            };
+            match received {
+                Err(err) => return Some((Err(SearchError::from(err)), socket)),
+                // Datagrams that are not text can not be SSDP responses, skip them.
+                Ok((n, source)) => {
+                    if let Ok(text) = str::from_utf8(&buf[..n]) {
+                        return Some((Ok((text.to_owned(), source)), socket));
+                    }
+                }
+            }
+        }
+    })
+}
+
+// An SSDP announcement sent by a device to the multicast group.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Announcement {
+    // The device is available, with its current boot id if it announces one.
+    Alive { addr: IpAddr, boot_id: Option<u32> },
+    // The device is about to change its boot id without rebooting.
+    Update { addr: IpAddr, next_boot_id: Option<u32> },
+    // The device is leaving the network.
+    ByeBye { addr: IpAddr },
+}
+
+impl Announcement {
+    // The address of the device, taken from the location of its description.
+    pub(crate) fn addr(&self) -> IpAddr {
+        match *self {
+            Announcement::Alive { addr, .. } | Announcement::Update { addr, .. } | Announcement::ByeBye { addr } => addr,
+        }
+    }
+}
+
+// Listen for the announcements sent to the SSDP multicast groups of the family of the given address.
+pub(crate) fn announcements(ip: IpAddr) -> BoxStream<'static, Result<Announcement, SearchError>> {
+    let listen = async move {
+        let socket = bind_reusable(SocketAddr::new(unspecified(&ip), SSDP_PORT))?;
+        match ip {
+            IpAddr::V4(_) => socket.join_multicast_v4(&SSDP_MULTICAST_V4, &Ipv4Addr::UNSPECIFIED)?,
+            IpAddr::V6(_) => {
+                for group in &SSDP_MULTICAST_V6 {
+                    socket.join_multicast_v6(group, 0)?;
+                }
+            }
+        }
+        Ok::<_, SearchError>(responses(UdpSocket::from_std(socket)?, None))
     };
-    let future = client.get(uri).and_then(|resp| resp.into_body().concat2()).then(|result| match result {
-        Ok(body) => parse_control_url(body.as_ref()),
-        Err(err) => Err(SearchError::from(err)),
-    });
-    Box::new(future)
+    let stream = stream::once(listen)
+        .try_flatten()
+        .try_filter_map(|(text, _)| future::ready(Ok(parse_announcement(&text))));
+    Box::pin(stream)
+}
+
+fn unspecified(ip: &IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        IpAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    }
+}
+
+// Bind a non-blocking UDP socket with address reuse, so that a well-known multicast port can be
+// shared with other listeners running on the host.
+pub(crate) fn bind_reusable(addr: SocketAddr) -> io::Result<std::net::UdpSocket> {
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_reuse_address(true)?;
+    reuse_port(&socket)?;
+    socket.bind(&addr.into())?;
+    socket.set_nonblocking(true)?;
+    Ok(socket.into())
+}
+
+#[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos", target_os = "cygwin"))))]
+fn reuse_port(socket: &Socket) -> io::Result<()> {
+    socket.set_reuse_port(true)
+}
+
+#[cfg(not(all(unix, not(any(target_os = "solaris", target_os = "illumos", target_os = "cygwin")))))]
+fn reuse_port(_socket: &Socket) -> io::Result<()> {
+    Ok(())
+}
+
+async fn get_services(transport: &dyn Transport, location: &(SocketAddr, String)) -> Result<Vec<Service>, SearchError> {
+    let uri: Uri = format!("http://{}{}", location.0, location.1).parse()?;
+    let req = Request::get(uri).body(Bytes::new()).unwrap();
+    let resp = transport.send(req).await?;
+    parse_services(resp.body().as_ref())
+}
+
+// Version of the connection service with the given type, if it can be used for port mapping.
+fn connection_service_version(service_type: &str) -> Option<u32> {
+    match service_type {
+        "urn:schemas-upnp-org:service:WANIPConnection:1" => Some(1),
+        "urn:schemas-upnp-org:service:WANIPConnection:2" => Some(2),
+        "urn:schemas-upnp-org:service:WANPPPConnection:1" => Some(1),
+        _ => None,
+    }
+}
+
+// A service listed in the description of a device.
+#[derive(Clone, Debug)]
+struct Service {
+    service_type: String,
+    control_url: String,
+    event_sub_url: String,
 }
 
-fn parse_control_url<R>(resp: R) -> Result<String, SearchError>
+// Build the gateway using the newest connection service of the device.
+fn gateway_from_services(addr: SocketAddr, services: &[Service]) -> Option<Gateway> {
+    let mut best: Option<(&Service, u32)> = None;
+    for service in services {
+        if let Some(version) = connection_service_version(&service.service_type) {
+            if best.is_none_or(|(_, best_version)| version > best_version) {
+                best = Some((service, version));
+            }
+        }
+    }
+    let (connection, _) = best?;
+    let mut gateway = Gateway::new(addr, connection.control_url.clone(), connection.service_type.clone());
+    if !connection.event_sub_url.is_empty() {
+        gateway = gateway.with_event_sub_url(connection.event_sub_url.clone());
+    }
+    if let Some(service) = services.iter().find(|s| s.service_type == "urn:schemas-upnp-org:service:WANCommonInterfaceConfig:1") {
+        gateway = gateway.with_common_interface_config(service.control_url.clone());
+    }
+    if let Some(service) = services.iter().find(|s| s.service_type == "urn:schemas-upnp-org:service:WANIPv6FirewallControl:1") {
+        gateway = gateway.with_firewall_control(service.control_url.clone());
+    }
+    Some(gateway)
+}
+
+// Parse the services with a control url listed in the description.
+fn parse_services<R>(resp: R) -> Result<Vec<Service>, SearchError>
 where
     R: io::Read,
 {
     let parser = EventReader::new(resp);
     let mut chain = Vec::<String>::with_capacity(4);
 
-    struct Service {
-        service_type: String,
-        control_url: String,
-    }
-
     let mut service = Service {
         service_type: "".to_string(),
         control_url: "".to_string(),
+        event_sub_url: "".to_string(),
     };
+    let mut services = Vec::new();
 
     for e in parser.into_iter() {
-        match r#try!(e) {
+        match e? {
             XmlEvent::StartElement { name, .. } => {
                 chain.push(name.borrow().to_repr());
                 let tail = if chain.len() >= 3 {
@@ -107,9 +434,10 @@ where
                     continue;
                 };
 
-                if vec!["device", "serviceList", "service"].iter().zip(tail).all(|(l, r)| l == r) {
+                if ["device", "serviceList", "service"].iter().zip(tail).all(|(l, r)| l == r) {
                     service.service_type.clear();
                     service.control_url.clear();
+                    service.event_sub_url.clear();
                 }
             }
             XmlEvent::EndElement { .. } => {
@@ -120,11 +448,8 @@ where
                     continue;
                 };
 
-                if vec!["device", "serviceList"].iter().zip(tail).all(|(l, r)| l == r)
-                    && ("urn:schemas-upnp-org:service:WANIPConnection:1" == service.service_type || "urn:schemas-upnp-org:service:WANPPPConnection:1" == service.service_type)
-                    && service.control_url.len() != 0
-                {
-                    return Ok(service.control_url);
+                if ["device", "serviceList"].iter().zip(tail).all(|(l, r)| l == r) && !service.control_url.is_empty() {
+                    services.push(service.clone());
                 }
             }
             XmlEvent::Characters(text) => {
@@ -134,45 +459,131 @@ where
                     continue;
                 };
 
-                if vec!["device", "serviceList", "service", "serviceType"].iter().zip(tail.clone()).all(|(l, r)| l == r) {
+                if ["device", "serviceList", "service", "serviceType"].iter().zip(tail.clone()).all(|(l, r)| l == r) {
                     service.service_type.push_str(&text);
                 }
-                if vec!["device", "serviceList", "service", "controlURL"].iter().zip(tail).all(|(l, r)| l == r) {
+                if ["device", "serviceList", "service", "controlURL"].iter().zip(tail.clone()).all(|(l, r)| l == r) {
                     service.control_url.push_str(&text);
                 }
+                if ["device", "serviceList", "service", "eventSubURL"].iter().zip(tail).all(|(l, r)| l == r) {
+                    service.event_sub_url.push_str(&text);
+                }
             }
             _ => (),
         }
     }
-    Err(SearchError::InvalidResponse)
+    Ok(services)
+}
+
+// A link-local IPv6 address only makes sense with the interface it is reached through. The
+// location of the description rarely gives it, so it is taken from the address the response came
+// from.
+fn with_scope_of(addr: SocketAddr, source: SocketAddr) -> SocketAddr {
+    match (addr, source) {
+        (SocketAddr::V6(addr), SocketAddr::V6(source)) if is_unicast_link_local(addr.ip()) && addr.scope_id() == 0 => {
+            SocketAddrV6::new(*addr.ip(), addr.port(), addr.flowinfo(), source.scope_id()).into()
+        }
+        _ => addr,
+    }
+}
+
+fn is_unicast_link_local(ip: &Ipv6Addr) -> bool {
+    ip.segments()[0] & 0xffc0 == 0xfe80
 }
 
 // Parse the result.
-pub fn parse_result(text: &str) -> Option<(SocketAddrV4, String)> {
+//
+// An IPv6 address may carry a zone id, `[fe80::1%253]`. Only numeric ones are kept as the scope
+// id of the address, the others are names of interfaces.
+pub fn parse_result(text: &str) -> Option<(SocketAddr, String)> {
     let re = Regex::new(
-        r"(?i:Location):\s*http://(\d+\.\d+\.\d+\.\d+):(\d+)(/[^\r]*)",
+        r"(?i:Location):\s*http://(?:(\d+\.\d+\.\d+\.\d+)|\[([0-9A-Fa-f:.]+)(?:%(?:25)?([0-9A-Za-z._~-]+))?\]):(\d+)(/[^\r]*)",
     ).unwrap();
     for line in text.lines() {
         match re.captures(line) {
             None => continue,
             Some(cap) => {
-                // these shouldn't fail if the regex matched.
-                let addr = &cap[1];
-                let port = &cap[2];
-                return Some((
-                    SocketAddrV4::new(
-                        addr.parse::<Ipv4Addr>().unwrap(),
-                        port.parse::<u16>().unwrap(),
-                    ),
-                    cap[3].to_string(),
-                ));
+                // these shouldn't fail if the regex matched, apart from a malformed IPv6 address.
+                let port = cap[4].parse::<u16>().ok()?;
+                let addr = match (cap.get(1), cap.get(2)) {
+                    (Some(v4), _) => SocketAddr::new(IpAddr::V4(v4.as_str().parse::<Ipv4Addr>().ok()?), port),
+                    (_, Some(v6)) => {
+                        let scope_id = cap.get(3).and_then(|zone| zone.as_str().parse().ok()).unwrap_or(0);
+                        SocketAddrV6::new(v6.as_str().parse::<Ipv6Addr>().ok()?, port, 0, scope_id).into()
+                    }
+                    _ => continue,
+                };
+                return Some((addr, cap[5].to_string()));
             }
         }
     }
     None
 }
 
+// Get the value of a header in the response.
+fn parse_header<'a>(text: &'a str, name: &str) -> Option<&'a str> {
+    text.lines().find_map(|line| {
+        let mut parts = line.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some(key), Some(value)) if key.trim().eq_ignore_ascii_case(name) => Some(value.trim()),
+            _ => None,
+        }
+    })
+}
+
+// Parse the unique service name of the responding device.
+fn parse_usn(text: &str) -> Option<String> {
+    parse_header(text, "USN").filter(|usn| !usn.is_empty()).map(|usn| usn.to_owned())
+}
+
+// Parse a boot id header, BOOTID.UPNP.ORG or NEXTBOOTID.UPNP.ORG.
+fn parse_boot_id(text: &str, name: &str) -> Option<u32> {
+    parse_header(text, name).and_then(|boot_id| boot_id.parse().ok())
+}
+
+// Parse a NOTIFY request sent by a device to the multicast group.
+fn parse_announcement(text: &str) -> Option<Announcement> {
+    if !text.starts_with("NOTIFY ") {
+        return None;
+    }
+    let addr = parse_result(text)?.0.ip();
+    match parse_header(text, "NTS")? {
+        "ssdp:alive" => Some(Announcement::Alive {
+            addr,
+            boot_id: parse_boot_id(text, "BOOTID.UPNP.ORG"),
+        }),
+        "ssdp:update" => Some(Announcement::Update {
+            addr,
+            next_boot_id: parse_boot_id(text, "NEXTBOOTID.UPNP.ORG"),
+        }),
+        "ssdp:byebye" => Some(Announcement::ByeBye { addr }),
+        _ => None,
+    }
+}
+
+// Devices other than gateways sometimes answer the search, the search target tells them apart.
+fn is_gateway_response(text: &str) -> bool {
+    match parse_header(text, "ST") {
+        Some(st) => st.contains("InternetGatewayDevice"),
+        None => true,
+    }
+}
+
+#[cfg(test)]
 mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bind_search_interface() {
+        let socket = Socket::from(bind_search(Ipv6Addr::UNSPECIFIED.into(), 0).unwrap());
+        assert_eq!(socket.multicast_if_v6().unwrap(), 0);
+        // The loopback interface is the only one known to exist.
+        let loopback = Ipv6Addr::LOCALHOST.into();
+        let socket = Socket::from(bind_search(loopback, 1).unwrap());
+        assert_eq!(socket.multicast_if_v6().unwrap(), 1);
+        assert_eq!(socket.local_addr().unwrap().as_socket().unwrap().ip(), loopback);
+    }
+
     #[test]
     fn test_parse_result_case_insensitivity() {
         assert!(parse_result("location:http://0.0.0.0:0/control_url").is_some());
@@ -182,8 +593,151 @@ mod tests {
     #[test]
     fn test_parse_result() {
         let result = parse_result("location:http://0.0.0.0:0/control_url").unwrap();
-        assert_eq!(result.0.ip(), &Ipv4Addr::new(0, 0, 0, 0));
+        assert_eq!(result.0.ip(), IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)));
         assert_eq!(result.0.port(), 0);
         assert_eq!(&result.1[..], "/control_url");
     }
+
+    #[test]
+    fn test_parse_result_ipv6() {
+        let result = parse_result("LOCATION: http://[fe80::1]:5000/rootDesc.xml").unwrap();
+        assert_eq!(result.0.ip(), IpAddr::V6("fe80::1".parse().unwrap()));
+        assert_eq!(result.0.port(), 5000);
+        assert_eq!(&result.1[..], "/rootDesc.xml");
+        assert!(parse_result("LOCATION: http://[fe80::zz]:5000/rootDesc.xml").is_none());
+    }
+
+    #[test]
+    fn test_parse_result_zone_id() {
+        let result = parse_result("LOCATION: http://[fe80::1%253]:5000/rootDesc.xml").unwrap();
+        assert_eq!(result.0, "[fe80::1%3]:5000".parse().unwrap());
+        assert_eq!(&result.1[..], "/rootDesc.xml");
+        let result = parse_result("LOCATION: http://[fe80::1%25eth0]:5000/rootDesc.xml").unwrap();
+        assert_eq!(result.0, "[fe80::1]:5000".parse().unwrap());
+
+        // Link-local addresses without a scope take the one of the response.
+        let source = "[fe80::1%2]:1900".parse().unwrap();
+        assert_eq!(with_scope_of(result.0, source), "[fe80::1%2]:5000".parse().unwrap());
+        let global = "[2001:db8::1]:5000".parse().unwrap();
+        assert_eq!(with_scope_of(global, source), global);
+        let scoped = "[fe80::1%3]:5000".parse().unwrap();
+        assert_eq!(with_scope_of(scoped, source), scoped);
+    }
+
+    #[test]
+    fn test_search_request_host() {
+        let targets = search_targets(&IpAddr::V6(Ipv6Addr::UNSPECIFIED));
+        assert_eq!(targets.len(), 2);
+        assert!(search_request(&targets[0], SEARCH_TARGETS[0]).contains("Host:[ff02::c]:1900\r\n"));
+        let targets = search_targets(&IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        assert!(search_request(&targets[0], SEARCH_TARGETS[0]).contains("Host:239.255.255.250:1900\r\n"));
+    }
+
+    #[test]
+    fn test_gateway_from_services_prefers_newest_version() {
+        let description = r#"<?xml version="1.0"?>
+<root xmlns="urn:schemas-upnp-org:device-1-0">
+  <device>
+    <deviceType>urn:schemas-upnp-org:device:WANDevice:2</deviceType>
+    <serviceList>
+      <service>
+        <serviceType>urn:schemas-upnp-org:service:WANCommonInterfaceConfig:1</serviceType>
+        <controlURL>/ctl/CmnIfCfg</controlURL>
+      </service>
+    </serviceList>
+  </device>
+  <device>
+    <deviceType>urn:schemas-upnp-org:device:WANConnectionDevice:2</deviceType>
+    <serviceList>
+      <service>
+        <serviceType>urn:schemas-upnp-org:service:WANPPPConnection:1</serviceType>
+        <controlURL>/ctl/PPPConn</controlURL>
+      </service>
+      <service>
+        <serviceType>urn:schemas-upnp-org:service:WANIPConnection:2</serviceType>
+        <controlURL>/ctl/IPConn</controlURL>
+        <eventSubURL>/evt/IPConn</eventSubURL>
+      </service>
+      <service>
+        <serviceType>urn:schemas-upnp-org:service:WANIPv6FirewallControl:1</serviceType>
+        <controlURL>/ctl/IP6FCtl</controlURL>
+      </service>
+    </serviceList>
+  </device>
+</root>"#;
+        let services = parse_services(description.as_bytes()).unwrap();
+        let gateway = gateway_from_services("192.168.1.1:5000".parse().unwrap(), &services).unwrap();
+        assert_eq!(gateway.control_url(), "/ctl/IPConn");
+        assert_eq!(gateway.service_type(), "urn:schemas-upnp-org:service:WANIPConnection:2");
+        assert_eq!(gateway.common_interface_control_url(), Some("/ctl/CmnIfCfg".to_owned()));
+        assert_eq!(gateway.firewall_control_url(), Some("/ctl/IP6FCtl".to_owned()));
+        assert_eq!(gateway.event_sub_url(), Some("/evt/IPConn".to_owned()));
+    }
+
+    #[test]
+    fn test_gateway_from_services_keeps_ppp_service_type() {
+        let description = r#"<?xml version="1.0"?>
+<root xmlns="urn:schemas-upnp-org:device-1-0">
+  <device>
+    <serviceList>
+      <service>
+        <serviceType>urn:schemas-upnp-org:service:WANPPPConnection:1</serviceType>
+        <controlURL>/upnp/control/WANPPPConn1</controlURL>
+      </service>
+    </serviceList>
+  </device>
+</root>"#;
+        let services = parse_services(description.as_bytes()).unwrap();
+        let gateway = gateway_from_services("192.168.1.1:5000".parse().unwrap(), &services).unwrap();
+        assert_eq!(gateway.control_url(), "/upnp/control/WANPPPConn1");
+        assert_eq!(gateway.service_type(), "urn:schemas-upnp-org:service:WANPPPConnection:1");
+        assert_eq!(gateway.common_interface_control_url(), None);
+        assert_eq!(gateway.event_sub_url(), None);
+    }
+
+    #[test]
+    fn test_parse_usn() {
+        let text = "HTTP/1.1 200 OK\r\nusn: uuid:1234::urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\n\r\n";
+        assert_eq!(
+            parse_usn(text).unwrap(),
+            "uuid:1234::urn:schemas-upnp-org:device:InternetGatewayDevice:1"
+        );
+        assert!(parse_usn("HTTP/1.1 200 OK\r\n\r\n").is_none());
+    }
+
+    #[test]
+    fn test_parse_announcement() {
+        let alive = "NOTIFY * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nLOCATION: http://192.168.1.1:5000/rootDesc.xml\r\nNT: upnp:rootdevice\r\nNTS: ssdp:alive\r\nBOOTID.UPNP.ORG: 1700000000\r\n\r\n";
+        assert_eq!(
+            parse_announcement(alive),
+            Some(Announcement::Alive {
+                addr: "192.168.1.1".parse().unwrap(),
+                boot_id: Some(1_700_000_000),
+            })
+        );
+        let update = "NOTIFY * HTTP/1.1\r\nLOCATION: http://192.168.1.1:5000/rootDesc.xml\r\nNTS: ssdp:update\r\nBOOTID.UPNP.ORG: 7\r\nNEXTBOOTID.UPNP.ORG: 8\r\n\r\n";
+        assert_eq!(
+            parse_announcement(update),
+            Some(Announcement::Update {
+                addr: "192.168.1.1".parse().unwrap(),
+                next_boot_id: Some(8),
+            })
+        );
+        let search = "M-SEARCH * HTTP/1.1\r\nHost:239.255.255.250:1900\r\nST:ssdp:all\r\n\r\n";
+        assert_eq!(parse_announcement(search), None);
+    }
+
+    #[test]
+    fn test_bind_reusable_shares_port() {
+        let first = bind_reusable("127.0.0.1:0".parse().unwrap()).unwrap();
+        let second = bind_reusable(first.local_addr().unwrap()).unwrap();
+        assert_eq!(first.local_addr().unwrap(), second.local_addr().unwrap());
+    }
+
+    #[test]
+    fn test_is_gateway_response() {
+        assert!(is_gateway_response("ST: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\n"));
+        assert!(!is_gateway_response("ST: urn:schemas-upnp-org:device:MediaRenderer:1\r\n"));
+        assert!(is_gateway_response("LOCATION: http://10.0.0.1:80/desc.xml\r\n"));
+    }
 }
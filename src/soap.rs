@@ -1,15 +1,18 @@
 use std::io;
 use std::string::FromUtf8Error;
 
-use futures::future;
-use futures::{Future, Stream};
-use hyper;
-use hyper::error::Error as HyperError;
+use bytes::Bytes;
+use hyper::Error as HyperError;
 use hyper::header::{CONTENT_LENGTH, CONTENT_TYPE};
-use hyper::{Client, Request};
 use hyper::http::uri::InvalidUri;
+use hyper::{Request, Uri};
 use mime::TEXT_XML;
 
+use crate::errors::TransportError;
+use crate::runtime;
+use crate::{RequestPolicy, Transport};
+
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     HttpError(HyperError),
     IoError(io::Error),
@@ -41,25 +44,72 @@ impl From<FromUtf8Error> for Error {
     }
 }
 
-pub fn send_async(url: &str, action: &str, body: &str) -> Box<Future<Item = String, Error = Error>> {
-    let client = Client::new();
-    let uri: hyper::Uri = match url.parse() {
-        Ok(uri) => uri,
-        Err(err) => return Box::new(future::err(Error::from(err))),
-    };
-    let mut req = Request::builder();
-    let req = req
-        .method("POST")
-        .uri(uri)
-        .header("SOAPAction", action)
-        .header(CONTENT_TYPE, TEXT_XML.as_ref())
-        .header(CONTENT_LENGTH, body.len())
-        .body(hyper::Body::from(body.to_owned())).unwrap();
+impl From<TransportError> for Error {
+    fn from(err: TransportError) -> Error {
+        match err {
+            TransportError::HttpError(e) => Error::HttpError(e),
+            TransportError::IoError(e) | TransportError::ConnectError(e) => Error::IoError(e),
+        }
+    }
+}
+
+// Send the SOAP request, sending it again after a backoff while it fails with a transient error.
+//
+// Only the idempotent actions are sent again once they may have reached the gateway, the others
+// only when the connection could not be made.
+pub async fn send_async(transport: &dyn Transport, policy: &RequestPolicy, url: &str, action: &str, body: &str, idempotent: bool) -> Result<String, Error> {
+    let uri: Uri = url.parse()?;
+    let body = Bytes::from(body.to_owned());
+    let mut retry = 0;
+    loop {
+        let mut req = Request::builder()
+            .method("POST")
+            .uri(uri.clone())
+            .header("SOAPAction", action)
+            .header(CONTENT_TYPE, TEXT_XML.as_ref())
+            .header(CONTENT_LENGTH, body.len())
+            .body(body.clone())
+            .unwrap();
+        req.extensions_mut().insert(policy.clone());
+
+        match transport.send(req).await {
+            Ok(resp) => return Ok(String::from_utf8(resp.into_body().to_vec())?),
+            Err(err) if retry < policy.retries && is_transient(&err, idempotent) => {
+                runtime::sleep(policy.backoff(retry)).await;
+                retry += 1;
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+// Whether the request may succeed if sent again: the connection failed, was lost or timed out.
+fn is_transient(err: &TransportError, idempotent: bool) -> bool {
+    match err {
+        TransportError::ConnectError(e) => is_transient_io(e),
+        TransportError::IoError(e) => idempotent && is_transient_io(e),
+        TransportError::HttpError(e) => {
+            idempotent
+                && (e.is_incomplete_message()
+                    || e.is_closed()
+                    || e.is_canceled()
+                    || e.is_timeout()
+                    || std::error::Error::source(e)
+                        .and_then(|source| source.downcast_ref::<io::Error>())
+                        .is_some_and(is_transient_io))
+        }
+    }
+}
 
-    let future = client
-        .request(req)
-        .and_then(|resp| resp.into_body().concat2())
-        .map_err(|err| Error::from(err))
-        .and_then(|bytes| String::from_utf8(bytes.to_vec()).map_err(|err| Error::from(err)));
-    Box::new(future)
+fn is_transient_io(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::TimedOut
+            | io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::Interrupted
+    )
 }
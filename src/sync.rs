@@ -0,0 +1,343 @@
+//! Blocking versions of the gateway and the search functions.
+//!
+//! Every call runs the asynchronous version to completion on the current thread, on a runtime
+//! owned by the call with tokio, so these functions can be used without setting up a runtime.
+//! They must not be called from within an asynchronous task.
+
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::{Stream, TryStreamExt};
+
+use crate::errors::{
+    AddAnyPortError, AddPortError, GetConnectionInfoError, GetExternalIpError, GetGenericPortMappingEntryError, GetInterfaceConfigError, GetPortMappingError, GetStatusInfoError, ListPortMappingsError, PinholeError, RemovePortError, SearchError,
+};
+use crate::gateway::{ConnectionTypeInfo, NatRsipStatus, PortMappingEntry, RemovePortRangeReport, StatusInfo};
+use crate::interface_config::CommonLinkProperties;
+use crate::runtime::block_on;
+use crate::{search, FirewallStatus, PortMappingProtocol, RequestPolicy, SearchOptions, Transport};
+
+/// Search gateway, bind to all interfaces and use a timeout of 3 seconds.
+///
+/// Bind to all interfaces.
+/// The request will timeout after 3 seconds.
+pub fn search_gateway() -> Result<Gateway, SearchError> {
+    block_on(search::search_gateway()).map(Gateway::from)
+}
+
+/// Search gateway, bind to all interfaces and use the given duration for the timeout.
+///
+/// Bind to all interfaces.
+/// The request will timeout after the given duration.
+pub fn search_gateway_timeout(timeout: Duration) -> Result<Gateway, SearchError> {
+    block_on(search::search_gateway_timeout(timeout)).map(Gateway::from)
+}
+
+/// Search gateway, bind to the given interface and use a time of 3 seconds.
+///
+/// Bind to the given interface, which can be an IPv4 or an IPv6 address.
+/// The request will timeout after 3 seconds.
+pub fn search_gateway_from(ip: impl Into<IpAddr>) -> Result<Gateway, SearchError> {
+    block_on(search::search_gateway_from(ip)).map(Gateway::from)
+}
+
+/// Search gateway, bind to the given interface and use the given duration for the timeout.
+///
+/// Bind to the given interface, which can be an IPv4 or an IPv6 address.
+/// The request will timeout after the given duration.
+pub fn search_gateway_from_timeout(ip: impl Into<IpAddr>, timeout: Duration) -> Result<Gateway, SearchError> {
+    block_on(search::search_gateway_from_timeout(ip, timeout)).map(Gateway::from)
+}
+
+/// Search gateway, bind to the given interface and use the given duration for the timeout.
+///
+/// The description of the gateway is read with the given transport, and the requests to the
+/// gateway found are sent with it.
+pub fn search_gateway_with_transport(ip: impl Into<IpAddr>, timeout: Duration, transport: Arc<dyn Transport>) -> Result<Gateway, SearchError> {
+    block_on(search::search_gateway_with_transport(ip, timeout, transport)).map(Gateway::from)
+}
+
+/// Search gateway with the given options.
+///
+/// See `igd::search_gateway_with_options`.
+pub fn search_gateway_with_options(options: SearchOptions) -> Result<Gateway, SearchError> {
+    block_on(search::search_gateway_with_options(options)).map(Gateway::from)
+}
+
+/// Search all gateways, bind to all interfaces and listen for 3 seconds.
+///
+/// Bind to all interfaces.
+/// Returns the gateways found after 3 seconds.
+pub fn search_gateways() -> Result<Vec<Gateway>, SearchError> {
+    collect_gateways(search::search_gateways())
+}
+
+/// Search all gateways, bind to all interfaces and listen for the given duration.
+///
+/// Bind to all interfaces.
+/// Returns the gateways found after the given duration.
+pub fn search_gateways_timeout(timeout: Duration) -> Result<Vec<Gateway>, SearchError> {
+    collect_gateways(search::search_gateways_timeout(timeout))
+}
+
+/// Search all gateways, bind to the given interface and listen for 3 seconds.
+///
+/// Bind to the given interface, which can be an IPv4 or an IPv6 address.
+/// Returns the gateways found after 3 seconds.
+pub fn search_gateways_from(ip: impl Into<IpAddr>) -> Result<Vec<Gateway>, SearchError> {
+    collect_gateways(search::search_gateways_from(ip))
+}
+
+/// Search all gateways, bind to the given interface and listen for the given duration.
+///
+/// Bind to the given interface, which can be an IPv4 or an IPv6 address.
+/// Returns the gateways found after the given duration.
+pub fn search_gateways_from_timeout(ip: impl Into<IpAddr>, timeout: Duration) -> Result<Vec<Gateway>, SearchError> {
+    collect_gateways(search::search_gateways_from_timeout(ip, timeout))
+}
+
+/// Search all gateways, bind to the given interface and listen for the given duration.
+///
+/// The descriptions of the gateways are read with the given transport, and the requests to the
+/// gateways found are sent with it.
+pub fn search_gateways_with_transport(ip: impl Into<IpAddr>, timeout: Duration, transport: Arc<dyn Transport>) -> Result<Vec<Gateway>, SearchError> {
+    collect_gateways(search::search_gateways_with_transport(ip, timeout, transport))
+}
+
+/// Search all gateways with the given options.
+///
+/// Returns the gateways found once the timeout of the options has passed.
+pub fn search_gateways_with_options(options: SearchOptions) -> Result<Vec<Gateway>, SearchError> {
+    collect_gateways(search::search_gateways_with_options(options))
+}
+
+fn collect_gateways<S: Stream<Item = Result<crate::Gateway, SearchError>>>(gateways: S) -> Result<Vec<Gateway>, SearchError> {
+    block_on(gateways.map_ok(Gateway::from).try_collect())
+}
+
+/// A gateway whose methods block until the gateway answers.
+///
+/// This wraps the asynchronous `igd::Gateway`, see its methods for details.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Gateway {
+    inner: crate::Gateway,
+}
+
+impl Gateway {
+    /// Create a new Gateway
+    ///
+    /// The service type is the URN of the connection service at the control url, for instance
+    /// `urn:schemas-upnp-org:service:WANIPConnection:1` or `urn:schemas-upnp-org:service:WANPPPConnection:1`.
+    pub fn new(addr: SocketAddr, control_url: String, service_type: String) -> Gateway {
+        Gateway::from(crate::Gateway::new(addr, control_url, service_type))
+    }
+
+    /// Set the HTTP client the requests to the gateway are sent with, `HyperTransport` by default.
+    pub fn with_transport(self, transport: Arc<dyn Transport>) -> Gateway {
+        Gateway::from(self.inner.with_transport(transport))
+    }
+
+    /// Set the timeouts and retries of the requests to the gateway, `RequestPolicy::default()` by default.
+    pub fn with_request_policy(self, policy: RequestPolicy) -> Gateway {
+        Gateway::from(self.inner.with_request_policy(policy))
+    }
+
+    /// Set how many requests may be sent to the gateway at the same time, unlimited by default.
+    ///
+    /// The limit is shared by the gateway and all its clones, see `igd::Gateway::with_max_in_flight_requests`.
+    pub fn with_max_in_flight_requests(self, limit: usize) -> Gateway {
+        Gateway::from(self.inner.with_max_in_flight_requests(limit))
+    }
+
+    /// Get the asynchronous gateway wrapped by this one.
+    pub fn as_async(&self) -> &crate::Gateway {
+        &self.inner
+    }
+
+    /// Convert this gateway into an asynchronous one.
+    pub fn into_async(self) -> crate::Gateway {
+        self.inner
+    }
+
+    /// Get the IP address of the gateway
+    pub fn ip_addr(&self) -> IpAddr {
+        self.inner.ip_addr()
+    }
+
+    /// Get the control url of the gateway
+    pub fn control_url(&self) -> String {
+        self.inner.control_url()
+    }
+
+    /// Get the type of the connection service of the gateway
+    pub fn service_type(&self) -> String {
+        self.inner.service_type()
+    }
+
+    /// Get the version of the connection service of the gateway
+    pub fn version(&self) -> u32 {
+        self.inner.version()
+    }
+
+    /// Get the external IP address of the gateway.
+    pub fn get_external_ip(&self) -> Result<Ipv4Addr, GetExternalIpError> {
+        block_on(self.inner.get_external_ip())
+    }
+
+    /// Get the connection status, the last connection error and the uptime of the gateway.
+    pub fn status_info(&self) -> Result<StatusInfo, GetStatusInfoError> {
+        block_on(self.inner.status_info())
+    }
+
+    /// Get the NAT and RSIP status of the gateway.
+    pub fn nat_rsip_status(&self) -> Result<NatRsipStatus, GetConnectionInfoError> {
+        block_on(self.inner.nat_rsip_status())
+    }
+
+    /// Get the current and the possible connection types of the gateway.
+    pub fn connection_type_info(&self) -> Result<ConnectionTypeInfo, GetConnectionInfoError> {
+        block_on(self.inner.connection_type_info())
+    }
+
+    /// Get the number of port mappings of the gateway.
+    pub fn port_mapping_number_of_entries(&self) -> Result<u32, GetConnectionInfoError> {
+        block_on(self.inner.port_mapping_number_of_entries())
+    }
+
+    /// Get an external socket address with our external ip and any port.
+    ///
+    /// See `igd::Gateway::get_any_address`.
+    pub fn get_any_address(&self, protocol: PortMappingProtocol, local_addr: SocketAddrV4, lease_duration: u32, description: &str) -> Result<SocketAddrV4, AddAnyPortError> {
+        block_on(self.inner.get_any_address(protocol, local_addr, lease_duration, description))
+    }
+
+    /// Add a port mapping with any external port.
+    ///
+    /// See `igd::Gateway::add_any_port`.
+    pub fn add_any_port(&self, protocol: PortMappingProtocol, local_addr: SocketAddrV4, lease_duration: u32, description: &str) -> Result<u16, AddAnyPortError> {
+        block_on(self.inner.add_any_port(protocol, local_addr, lease_duration, description))
+    }
+
+    /// Add a port mapping.
+    ///
+    /// See `igd::Gateway::add_port`.
+    pub fn add_port(&self, protocol: PortMappingProtocol, external_port: u16, local_addr: SocketAddrV4, lease_duration: u32, description: &str) -> Result<(), AddPortError> {
+        block_on(self.inner.add_port(protocol, external_port, local_addr, lease_duration, description))
+    }
+
+    /// Remove a port mapping.
+    pub fn remove_port(&self, protocol: PortMappingProtocol, external_port: u16) -> Result<(), RemovePortError> {
+        block_on(self.inner.remove_port(protocol, external_port))
+    }
+
+    /// Get one port mapping entry of the gateway by its index.
+    pub fn get_generic_port_mapping_entry(&self, index: u32) -> Result<PortMappingEntry, GetGenericPortMappingEntryError> {
+        block_on(self.inner.get_generic_port_mapping_entry(index))
+    }
+
+    /// Get the port mapping entry for the given protocol, external port and remote host.
+    pub fn get_port_mapping(&self, protocol: PortMappingProtocol, external_port: u16, remote_host: &str) -> Result<PortMappingEntry, GetPortMappingError> {
+        block_on(self.inner.get_port_mapping(protocol, external_port, remote_host))
+    }
+
+    /// Get all the port mappings of the gateway.
+    pub fn port_mappings(&self) -> Result<Vec<PortMappingEntry>, GetGenericPortMappingEntryError> {
+        block_on(self.inner.port_mappings().try_collect())
+    }
+
+    /// Get the port mappings of the gateway with an external port in the given range.
+    ///
+    /// See `igd::Gateway::list_port_mappings`.
+    pub fn list_port_mappings(&self, start_port: u16, end_port: u16, protocol: PortMappingProtocol, manage: bool, max_entries: u16) -> Result<Vec<PortMappingEntry>, ListPortMappingsError> {
+        block_on(self.inner.list_port_mappings(start_port, end_port, protocol, manage, max_entries))
+    }
+
+    /// Remove the port mappings with an external port in the given range.
+    ///
+    /// See `igd::Gateway::remove_port_range`.
+    pub fn remove_port_range(&self, protocol: PortMappingProtocol, start_port: u16, end_port: u16, manage: bool) -> Result<RemovePortRangeReport, ListPortMappingsError> {
+        block_on(self.inner.remove_port_range(protocol, start_port, end_port, manage))
+    }
+
+    /// Get the access type, the maximum bit rates and the status of the physical WAN link of the gateway.
+    pub fn common_link_properties(&self) -> Result<CommonLinkProperties, GetInterfaceConfigError> {
+        block_on(self.inner.common_link_properties())
+    }
+
+    /// Get the number of bytes sent on the WAN link of the gateway.
+    pub fn total_bytes_sent(&self) -> Result<u64, GetInterfaceConfigError> {
+        block_on(self.inner.total_bytes_sent())
+    }
+
+    /// Get the number of bytes received on the WAN link of the gateway.
+    pub fn total_bytes_received(&self) -> Result<u64, GetInterfaceConfigError> {
+        block_on(self.inner.total_bytes_received())
+    }
+
+    /// Get the number of packets sent on the WAN link of the gateway.
+    pub fn total_packets_sent(&self) -> Result<u64, GetInterfaceConfigError> {
+        block_on(self.inner.total_packets_sent())
+    }
+
+    /// Get the number of packets received on the WAN link of the gateway.
+    pub fn total_packets_received(&self) -> Result<u64, GetInterfaceConfigError> {
+        block_on(self.inner.total_packets_received())
+    }
+
+    /// Get whether the IPv6 firewall of the gateway is enabled and accepts inbound pinholes.
+    pub fn firewall_status(&self) -> Result<FirewallStatus, PinholeError> {
+        block_on(self.inner.firewall_status())
+    }
+
+    /// Get how long the gateway keeps an outbound pinhole open without traffic.
+    pub fn outbound_pinhole_timeout(&self, protocol: PortMappingProtocol, remote_addr: SocketAddrV6, internal_addr: SocketAddrV6) -> Result<Duration, PinholeError> {
+        block_on(self.inner.outbound_pinhole_timeout(protocol, remote_addr, internal_addr))
+    }
+
+    /// Open an inbound IPv6 pinhole and return its unique id.
+    ///
+    /// See `igd::Gateway::add_pinhole`.
+    pub fn add_pinhole(&self, protocol: PortMappingProtocol, remote_addr: SocketAddrV6, internal_addr: SocketAddrV6, lease_time: u32) -> Result<u16, PinholeError> {
+        block_on(self.inner.add_pinhole(protocol, remote_addr, internal_addr, lease_time))
+    }
+
+    /// Extend the lease of a pinhole.
+    pub fn update_pinhole(&self, unique_id: u16, lease_time: u32) -> Result<(), PinholeError> {
+        block_on(self.inner.update_pinhole(unique_id, lease_time))
+    }
+
+    /// Close a pinhole.
+    pub fn delete_pinhole(&self, unique_id: u16) -> Result<(), PinholeError> {
+        block_on(self.inner.delete_pinhole(unique_id))
+    }
+
+    /// Check whether traffic goes through a pinhole.
+    pub fn check_pinhole_working(&self, unique_id: u16) -> Result<bool, PinholeError> {
+        block_on(self.inner.check_pinhole_working(unique_id))
+    }
+
+    /// Get the number of packets that went through a pinhole.
+    pub fn pinhole_packets(&self, unique_id: u16) -> Result<u32, PinholeError> {
+        block_on(self.inner.pinhole_packets(unique_id))
+    }
+}
+
+impl From<crate::Gateway> for Gateway {
+    fn from(inner: crate::Gateway) -> Gateway {
+        Gateway { inner }
+    }
+}
+
+impl From<Gateway> for crate::Gateway {
+    fn from(gateway: Gateway) -> crate::Gateway {
+        gateway.inner
+    }
+}
+
+impl fmt::Display for Gateway {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
@@ -0,0 +1,356 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::pin::{pin, Pin};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::task::Poll;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use futures::future::{self, BoxFuture, Either};
+use futures::Future;
+use futures::FutureExt;
+use http_body_util::{BodyExt, Full};
+use hyper::client::conn::http1;
+use hyper::header::HOST;
+use hyper::client::conn::TrySendError;
+use hyper::{Request, Response, Uri};
+
+use crate::errors::TransportError;
+use crate::runtime;
+use crate::RequestPolicy;
+
+/// The HTTP client the requests to gateways are sent with.
+///
+/// Implement it to send the requests through a connector of your own, from a given local address,
+/// or to record the traffic, and pass it to `Gateway::with_transport` or to the
+/// `search_gateway_with_transport` functions. `HyperTransport` is used by default.
+pub trait Transport: fmt::Debug + Send + Sync {
+    /// Send the request and read the whole response.
+    ///
+    /// The uri of the request is absolute, like `http://192.168.1.1:5000/ctl/IPConn`. SOAP actions
+    /// are POST requests, the descriptions of the devices are read with GET requests, and the
+    /// events are subscribed to with the SUBSCRIBE and UNSUBSCRIBE methods of GENA.
+    ///
+    /// The `RequestPolicy` of the gateway is in the extensions of the SOAP requests. Its connect and
+    /// read timeouts should be followed, failing with an IO error of kind `TimedOut`. The retries
+    /// are made by the caller: a request that failed with `TransportError::ConnectError` is sent
+    /// again, as it never reached the gateway, while after other errors only the requests that
+    /// read the state of the gateway are.
+    fn send(&self, request: Request<Bytes>) -> BoxFuture<'_, Result<Response<Bytes>, TransportError>>;
+}
+
+/// The default transport, sending the requests over HTTP/1.1 with hyper.
+///
+/// The connections are kept open once the response is read, and reused for the next requests to
+/// the same host until the gateway closes them. Clones of the transport share their connections,
+/// as do all the gateways that were not given a transport.
+///
+/// The timeouts are those of the `RequestPolicy` in the extensions of the request, or of the default
+/// policy if there is none.
+#[derive(Clone, Default)]
+pub struct HyperTransport {
+    // The idle connections by host, the most recently used last.
+    pool: Arc<Mutex<HashMap<String, Vec<Connection>>>>,
+}
+
+// How long a connection is kept without requests. Gateways often close theirs sooner.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+// How many idle connections are kept per host.
+const MAX_IDLE_PER_HOST: usize = 4;
+
+// An open connection to a host.
+struct Connection {
+    sender: http1::SendRequest<Full<Bytes>>,
+    connection: http1::Connection<runtime::TcpStream, Full<Bytes>>,
+    idle_since: Instant,
+}
+
+// The error of an exchange, with the request if it was not sent.
+struct ExchangeError {
+    error: TransportError,
+    request: Option<Request<Full<Bytes>>>,
+}
+
+impl From<hyper::Error> for ExchangeError {
+    fn from(err: hyper::Error) -> ExchangeError {
+        ExchangeError { error: err.into(), request: None }
+    }
+}
+
+impl From<io::Error> for ExchangeError {
+    fn from(err: io::Error) -> ExchangeError {
+        ExchangeError { error: err.into(), request: None }
+    }
+}
+
+impl From<TrySendError<Request<Full<Bytes>>>> for ExchangeError {
+    fn from(mut err: TrySendError<Request<Full<Bytes>>>) -> ExchangeError {
+        let request = err.take_message();
+        ExchangeError {
+            error: err.into_error().into(),
+            request,
+        }
+    }
+}
+
+impl HyperTransport {
+    /// Create a transport with connections of its own.
+    pub fn new() -> HyperTransport {
+        HyperTransport::default()
+    }
+
+    // Send the request on a connection to the host of its uri and read the whole response.
+    //
+    // The request is sent in origin form, with the authority of the uri in the Host header, which
+    // is what the HTTP servers of gateways expect.
+    async fn send_request(&self, req: Request<Bytes>) -> Result<Response<Bytes>, TransportError> {
+        let policy = req.extensions().get::<RequestPolicy>().cloned().unwrap_or_default();
+        let (mut parts, body) = req.into_parts();
+        let authority = match parts.uri.authority() {
+            Some(authority) => authority.clone(),
+            None => return Err(TransportError::IoError(io::Error::new(io::ErrorKind::InvalidInput, "the url has no host"))),
+        };
+        let host = format!("{}:{}", authority.host(), authority.port_u16().unwrap_or(80));
+        if let Some(path) = parts.uri.path_and_query().cloned() {
+            parts.uri = Uri::from(path);
+        }
+        if !parts.headers.contains_key(HOST) {
+            // The zone id of a link-local address only means something to this host.
+            let value = match (authority.as_str().find('%'), authority.as_str().find(']')) {
+                (Some(zone), Some(end)) if zone < end => format!("{}{}", &authority.as_str()[..zone], &authority.as_str()[end..]),
+                _ => authority.as_str().to_owned(),
+            };
+            parts.headers.insert(HOST, value.parse().unwrap());
+        }
+        let mut req = Request::from_parts(parts, Full::new(body));
+
+        if let Some(connection) = self.checkout(&host).await {
+            match self.exchange(&host, connection, req, policy.read_timeout).await {
+                Ok(resp) => return Ok(resp),
+                // The connection was closed before the request was sent, send it on a new one.
+                Err(ExchangeError { request: Some(request), .. }) => req = request,
+                Err(err) => return Err(err.error),
+            }
+        }
+
+        let stream = within(policy.connect_timeout, runtime::connect(host.clone())).await.map_err(TransportError::ConnectError)?;
+        let (sender, connection) = http1::handshake(stream).await?;
+        let connection = Connection {
+            sender,
+            connection,
+            idle_since: Instant::now(),
+        };
+        self.exchange(&host, connection, req, policy.read_timeout).await.map_err(|err| match err.request {
+            Some(_) => TransportError::ConnectError(io::Error::new(io::ErrorKind::ConnectionAborted, err.error.to_string())),
+            None => err.error,
+        })
+    }
+
+    // Send the request on the connection and read the whole response, then put the connection back
+    // in the pool if it is still open.
+    async fn exchange(&self, host: &str, connection: Connection, req: Request<Full<Bytes>>, read_timeout: Option<Duration>) -> Result<Response<Bytes>, ExchangeError> {
+        let Connection { mut sender, mut connection, .. } = connection;
+        let exchange = async {
+            let resp = sender.try_send_request(req).await?;
+            let (parts, body) = resp.into_parts();
+            let body = body.collect().await?.to_bytes();
+            Ok::<_, ExchangeError>(Response::from_parts(parts, body))
+        };
+        // The connection is driven alongside the exchange. It ends early if the gateway closes it,
+        // in which case the response may still be complete.
+        let (resp, open) = within(read_timeout, async {
+            match future::select(pin!(exchange), &mut connection).await {
+                Either::Left((resp, _)) => Ok::<_, ExchangeError>((resp?, true)),
+                Either::Right((_, exchange)) => Ok((exchange.await?, false)),
+            }
+        })
+        .await?;
+        if open && !sender.is_closed() {
+            self.checkin(host, Connection {
+                sender,
+                connection,
+                idle_since: Instant::now(),
+            });
+        }
+        Ok(resp)
+    }
+
+    // Take an idle connection to the host from the pool, if there is one still open.
+    async fn checkout(&self, host: &str) -> Option<Connection> {
+        loop {
+            let mut connection = {
+                let mut pool = self.pool.lock().unwrap();
+                let idle = pool.get_mut(host)?;
+                let connection = idle.pop();
+                if idle.is_empty() {
+                    pool.remove(host);
+                }
+                connection?
+            };
+            if connection.idle_since.elapsed() >= IDLE_TIMEOUT {
+                continue;
+            }
+            // The connections are not driven while idle, polling one notices if the gateway
+            // closed it meanwhile.
+            let closed = future::poll_fn(|cx| Poll::Ready(Pin::new(&mut connection.connection).poll(cx).is_ready())).await;
+            if !closed && connection.sender.is_ready() {
+                return Some(connection);
+            }
+        }
+    }
+
+    // Put the connection in the pool, unless there are enough idle connections to the host.
+    fn checkin(&self, host: &str, connection: Connection) {
+        let mut pool = self.pool.lock().unwrap();
+        let idle = pool.entry(host.to_owned()).or_default();
+        if idle.len() < MAX_IDLE_PER_HOST {
+            idle.push(connection);
+        }
+    }
+}
+
+impl Transport for HyperTransport {
+    fn send(&self, request: Request<Bytes>) -> BoxFuture<'_, Result<Response<Bytes>, TransportError>> {
+        self.send_request(request).boxed()
+    }
+}
+
+impl fmt::Debug for HyperTransport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let idle = self.pool.lock().map(|pool| pool.values().map(Vec::len).sum::<usize>()).unwrap_or(0);
+        f.debug_struct("HyperTransport").field("idle_connections", &idle).finish()
+    }
+}
+
+// The transport of the gateways that were not given one, shared by all of them.
+pub(crate) fn default_transport() -> Arc<dyn Transport> {
+    static DEFAULT: OnceLock<Arc<HyperTransport>> = OnceLock::new();
+    DEFAULT.get_or_init(|| Arc::new(HyperTransport::new())).clone()
+}
+
+// Run the future, failing with a TimedOut IO error if it takes longer than the timeout.
+async fn within<T, E, F>(timeout: Option<Duration>, future: F) -> Result<T, E>
+where
+    E: From<io::Error>,
+    F: Future<Output = Result<T, E>>,
+{
+    match timeout {
+        Some(timeout) => match runtime::timeout(timeout, future).await {
+            Ok(result) => result,
+            Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "the gateway did not answer in time").into()),
+        },
+        None => future.await,
+    }
+}
+
+// Transports answering the SOAP requests of the tests without a gateway.
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use futures::TryFutureExt;
+
+    type Respond = dyn Fn(&str, &Request<Bytes>) -> BoxFuture<'static, Result<String, TransportError>> + Send + Sync;
+
+    // The response of a gateway to the given action, with the given output arguments.
+    pub(crate) fn envelope(action: &str, args: &str) -> String {
+        format!(
+            "<s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\"><s:Body>\
+             <u:{0}Response xmlns:u=\"urn:schemas-upnp-org:service:WANIPConnection:1\">{1}</u:{0}Response>\
+             </s:Body></s:Envelope>",
+            action, args
+        )
+    }
+
+    // The response of a gateway failing an action with the given UPnP error code.
+    pub(crate) fn fault(code: u16) -> String {
+        format!(
+            "<s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\"><s:Body><s:Fault><detail>\
+             <UPnPError><errorCode>{}</errorCode><errorDescription>Refused</errorDescription></UPnPError>\
+             </detail></s:Fault></s:Body></s:Envelope>",
+            code
+        )
+    }
+
+    // A transport answering every request with the response a closure gives for its action, and
+    // recording the actions in the order they were sent.
+    pub(crate) struct FnTransport {
+        respond: Box<Respond>,
+        actions: Mutex<Vec<String>>,
+    }
+
+    impl FnTransport {
+        pub(crate) fn new<F>(respond: F) -> Arc<FnTransport>
+        where
+            F: Fn(&str, &Request<Bytes>) -> Result<String, TransportError> + Send + Sync + 'static,
+        {
+            FnTransport::new_async(move |action, request| future::ready(respond(action, request)).boxed())
+        }
+
+        // The closure returns a future, to answer later than right away.
+        pub(crate) fn new_async<F>(respond: F) -> Arc<FnTransport>
+        where
+            F: Fn(&str, &Request<Bytes>) -> BoxFuture<'static, Result<String, TransportError>> + Send + Sync + 'static,
+        {
+            Arc::new(FnTransport {
+                respond: Box::new(respond),
+                actions: Mutex::new(Vec::new()),
+            })
+        }
+
+        pub(crate) fn actions(&self) -> Vec<String> {
+            self.actions.lock().unwrap().clone()
+        }
+    }
+
+    impl fmt::Debug for FnTransport {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.debug_struct("FnTransport").field("actions", &self.actions).finish()
+        }
+    }
+
+    impl Transport for FnTransport {
+        fn send(&self, request: Request<Bytes>) -> BoxFuture<'_, Result<Response<Bytes>, TransportError>> {
+            let header = request.headers().get("SOAPAction").and_then(|v| v.to_str().ok()).unwrap_or("");
+            let action = header.trim_matches('"').rsplit('#').next().unwrap_or("").to_owned();
+            self.actions.lock().unwrap().push(action.clone());
+            (self.respond)(&action, &request).map_ok(|text| Response::new(Bytes::from(text))).boxed()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::{AsyncTcpListener, TcpListener};
+    use hyper::server::conn::http1 as server;
+    use hyper::service::service_fn;
+    use std::convert::Infallible;
+    use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+
+    #[test]
+    fn test_connections_are_reused() {
+        runtime::block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0".parse().unwrap()).await.unwrap();
+            let url = format!("http://{}/ctl/IPConn", listener.local_addr().unwrap());
+            let accepted = Arc::new(AtomicUsize::new(0));
+            let counter = accepted.clone();
+            runtime::spawn(async move {
+                while let Ok(stream) = listener.accept().await {
+                    counter.fetch_add(1, SeqCst);
+                    let service = service_fn(|_| future::ok::<_, Infallible>(Response::new(Full::new(Bytes::from_static(b"ok")))));
+                    runtime::spawn(async move {
+                        let _ = server::Builder::new().serve_connection(stream, service).await;
+                    });
+                }
+            });
+
+            let transport = HyperTransport::new();
+            for _ in 0..3 {
+                let resp = transport.send(Request::post(&url).body(Bytes::new()).unwrap()).await.unwrap();
+                assert_eq!(resp.into_body(), Bytes::from_static(b"ok"));
+            }
+            assert_eq!(accepted.load(SeqCst), 1);
+        });
+    }
+}